@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+
+// Used by the code `#[derive(TreeData)]` generates
+pub use fxhash::hash64;
+#[cfg(feature = "derive")]
+pub use vitree_derive::TreeData;
+
+// Field types that can be marked `#[tree(icon)]`. An empty icon is no icon
+pub trait AsIcon {
+    fn as_icon(&self) -> Option<&str>;
+}
+
+impl AsIcon for str {
+    fn as_icon(&self) -> Option<&str> {
+        Some(self).filter(|icon| !icon.is_empty())
+    }
+}
+
+impl AsIcon for String {
+    fn as_icon(&self) -> Option<&str> {
+        self.as_str().as_icon()
+    }
+}
+
+impl AsIcon for &'static str {
+    fn as_icon(&self) -> Option<&str> {
+        (**self).as_icon()
+    }
+}
+
+impl AsIcon for Cow<'static, str> {
+    fn as_icon(&self) -> Option<&str> {
+        (**self).as_icon()
+    }
+}
+
+impl<T: AsIcon> AsIcon for Option<T> {
+    fn as_icon(&self) -> Option<&str> {
+        self.as_ref().and_then(AsIcon::as_icon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_icon_is_none() {
+        assert_eq!(String::from("file").as_icon(), Some("file"));
+        assert_eq!("".as_icon(), None);
+        assert_eq!(Some(Cow::Borrowed("dir")).as_icon(), Some("dir"));
+        assert_eq!(None::<String>.as_icon(), None);
+        assert_eq!(Some(String::new()).as_icon(), None);
+    }
+}
@@ -1,14 +1,21 @@
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::{Rc, Weak};
 
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
 use indexmap::IndexMap;
 use skima::web::Callback;
-use wasm_bindgen::UnwrapThrowExt;
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsCast;
 
-use super::item::TreeItem;
-use super::node::{TreeFlags, TreeNode};
-use super::provider::{TreeExpandResult, TreeProvider};
-use super::view::TreeController;
+use super::item::{CheckState, TreeItem};
+use super::node::{TreeData, TreeFlags, TreeNode};
+use super::placeholder::PlaceholderData;
+use super::provider::{ProviderError, TreeProvider};
+use super::view::{ClickModifiers, DropPosition, DropTarget, TreeController};
 use super::KeyType;
 
 pub trait TreeSubscriber {
@@ -17,23 +24,181 @@ pub trait TreeSubscriber {
 
     // Update single item
     fn update_item(&self, key: usize);
+
+    // Update a batch of items collected during one frame
+    fn update_items(&self, keys: &[KeyType]) {
+        for key in keys {
+            self.update_item(*key)
+        }
+    }
+
+    // Rows were replaced in the visible list, rows outside of the change
+    // stay the same but the ones after it move
+    fn update_rows(&self, _change: &TreeChange) {
+        self.update_all()
+    }
+}
+
+// The rows in `removed` (indexes in the list before the change) were
+// replaced with the rows in `inserted` (indexes after it). Both start at the
+// same row, expanding a node inserts its children right after it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeChange {
+    pub removed: Range<usize>,
+    pub inserted: Range<usize>,
+}
+
+impl TreeChange {
+    pub fn start(&self) -> usize {
+        self.removed.start
+    }
+
+    // How much the rows after the change moved
+    pub fn shift(&self) -> isize {
+        self.inserted.len() as isize - self.removed.len() as isize
+    }
+}
+
+// Keeps a subscriber registered until dropped, see `DynamicTree::subscribe`
+#[must_use = "dropping the subscription unsubscribes immediately"]
+pub struct Subscription {
+    tree: Weak<DynamicTree>,
+    subscriber: Weak<dyn TreeSubscriber>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let (Some(tree), Some(subscriber)) = (self.tree.upgrade(), self.subscriber.upgrade())
+        else {
+            return;
+        };
+
+        tree.remove_subscriber(&subscriber);
+    }
 }
 
+pub type TreeFilter = Rc<dyn Fn(&dyn TreeData) -> bool>;
+
+// Runs the async work of the tree, `spawn_local` by default
+pub type Spawner = Rc<dyn Fn(LocalBoxFuture<'static, ()>)>;
+
 pub struct DynamicTree {
     this: Weak<Self>,
     root: Rc<TreeNode>,
     callbacks: TreeCallbacks,
-    flat: RefCell<IndexMap<usize, Rc<TreeNode>>>,
+    flat: RefCell<IndexMap<KeyType, Rc<TreeNode>>>,
+    total_height: Cell<Option<usize>>,
+    frozen: Cell<bool>,
+    selection: RefCell<BTreeSet<KeyType>>,
+    anchor: Cell<Option<KeyType>>,
+    pending_updates: RefCell<BTreeSet<KeyType>>,
+    sync_updates: Cell<bool>,
+    expand_on: Cell<ExpandOn>,
+    filter: RefCell<Option<TreeFilter>>,
+    // Ancestors of filter matches that were collapsed before the filter
+    auto_expanded: RefCell<BTreeSet<KeyType>>,
+    // Restored keys that aren't loaded yet, dropped once no load is in
+    // flight
+    pending_expansion: RefCell<BTreeSet<KeyType>>,
+    // Restored selection inside those folders, applied when they arrive
+    pending_selection: RefCell<BTreeSet<KeyType>>,
+    // Generation of the async load in flight for each node, results of
+    // older loads are dropped
+    loads: RefCell<HashMap<KeyType, usize>>,
+    load_generation: Cell<usize>,
+    spawner: RefCell<Spawner>,
     provider: Rc<dyn TreeProvider>,
     subscribers: RefCell<Vec<Weak<dyn TreeSubscriber>>>,
 }
 
+pub type ContextMenuCallback = Callback<dyn Fn(Rc<TreeNode>, i32, i32)>;
+pub type RenameCallback = Callback<dyn Fn(Rc<TreeNode>, String)>;
+pub type ExpandErrorCallback = Callback<dyn Fn(Rc<TreeNode>, ProviderError)>;
+pub type DropCallback = Callback<dyn Fn(Rc<TreeNode>, DropTarget)>;
+pub type HoverCallback = Callback<dyn Fn(Option<Rc<TreeNode>>)>;
+
 #[derive(Default)]
 pub struct TreeCallbacks {
     pub on_click: Option<Callback<dyn Fn(Rc<TreeNode>)>>,
+    pub on_dbl_click: Option<Callback<dyn Fn(Rc<TreeNode>)>>,
+    // Receives the client coordinates of the click to position the menu
+    pub on_context_menu: Option<ContextMenuCallback>,
+    // Fires when an inline edit is committed with a different title, the
+    // node itself is left to the consumer to update
+    pub on_rename: Option<RenameCallback>,
+    // Both fire right after the `EXPANDED` flag changes, before the list is
+    // re-flattened and subscribers get `update_all`. For async loads
+    // `on_expand` fires once, when the children have arrived
+    pub on_expand: Option<Callback<dyn Fn(Rc<TreeNode>)>>,
+    pub on_collapse: Option<Callback<dyn Fn(Rc<TreeNode>)>>,
+    pub on_expand_error: Option<ExpandErrorCallback>,
+    pub on_selection_change: Option<Callback<dyn Fn(SelectionChange)>>,
+    // Fires after a dragged node was moved, with the node it was dropped on
+    pub on_drop: Option<DropCallback>,
+    // The row under the pointer, `None` once it leaves the rows
+    pub on_hover: Option<HoverCallback>,
+}
+
+// Which click toggles expansion of a row, with `DoubleClick` a single click
+// only selects it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpandOn {
+    #[default]
+    Click,
+    DoubleClick,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectionChange {
+    pub added: Vec<KeyType>,
+    pub removed: Vec<KeyType>,
+    pub current: Vec<KeyType>,
+}
+
+// Expansion and selection of a tree, e.g. to reopen the same folders after
+// a reload
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeSnapshot {
+    pub expanded: Vec<KeyType>,
+    pub selected: Vec<KeyType>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    UnknownNode,
+    UnknownParent,
+    // The new parent is the node itself or one of its descendants
+    IntoDescendant,
+    // Dropped into a node that can't have children
+    NotAFolder,
+    // Dropped into a folder whose children aren't loaded, they would
+    // replace the node once they arrive
+    NotLoaded,
+    Frozen,
+}
+
+impl SelectionChange {
+    pub fn diff(old: &BTreeSet<KeyType>, new: &BTreeSet<KeyType>) -> Option<Self> {
+        if old == new {
+            return None;
+        }
+
+        Some(SelectionChange {
+            added: new.difference(old).copied().collect(),
+            removed: old.difference(new).copied().collect(),
+            current: new.iter().copied().collect(),
+        })
+    }
 }
 
 impl DynamicTree {
+    // Several top level nodes without a common parent row, see
+    // `TreeNode::forest`
+    pub fn forest(roots: Vec<Rc<TreeNode>>, callbacks: TreeCallbacks) -> Rc<Self> {
+        DynamicTree::new(TreeNode::forest(roots), callbacks)
+    }
+
     pub fn new(provider: Rc<dyn TreeProvider>, callbacks: TreeCallbacks) -> Rc<Self> {
         let root = provider.root();
         let flat = root.flatten();
@@ -45,6 +210,20 @@ impl DynamicTree {
             provider,
             subscribers: Default::default(),
             flat: RefCell::new(flat),
+            total_height: Cell::new(None),
+            frozen: Cell::new(false),
+            selection: Default::default(),
+            anchor: Cell::new(None),
+            pending_updates: Default::default(),
+            sync_updates: Cell::new(false),
+            expand_on: Cell::new(ExpandOn::Click),
+            filter: Default::default(),
+            auto_expanded: Default::default(),
+            pending_expansion: Default::default(),
+            pending_selection: Default::default(),
+            loads: Default::default(),
+            load_generation: Cell::new(0),
+            spawner: RefCell::new(Rc::new(wasm_bindgen_futures::spawn_local)),
         })
     }
 
@@ -52,56 +231,531 @@ impl DynamicTree {
         self.root.clone()
     }
 
+    // The visible rows in display order. The list is borrowed, so the guard
+    // has to be dropped before the tree is changed
+    pub fn visible(&self) -> Ref<'_, IndexMap<KeyType, Rc<TreeNode>>> {
+        self.flat.borrow()
+    }
+
     pub fn flatten(&self) {
-        self.flat.replace(self.root.flatten());
+        let mut flat = self.root.flatten();
+
+        if let Some(filter) = self.filter.borrow().as_ref() {
+            let keep = self.filter_matches(filter);
+            flat.retain(|key, node| {
+                if node.data_as::<PlaceholderData>().is_some() {
+                    // loading rows follow their node
+                    return node.parent().is_some_and(|p| keep.contains(&p.key()));
+                }
+
+                keep.contains(key)
+            });
+        }
+
+        self.flat.replace(flat);
+        self.total_height.set(None);
     }
 
-    fn for_each_subscriber(&self, func: impl Fn(&dyn TreeSubscriber)) {
-        self.subscribers
-            .borrow_mut()
-            .extract_if(|c| {
-                if let Some(c) = c.upgrade() {
-                    func(&*c);
-                    false
-                } else {
-                    true
+    // Keys of the matching nodes and of every ancestor on the way to them
+    fn filter_matches(&self, filter: &TreeFilter) -> BTreeSet<KeyType> {
+        let mut keep = BTreeSet::new();
+        self.root.walk(&mut |node| {
+            if !filter(&*node.data()) {
+                return;
+            }
+
+            keep.insert(node.key());
+            for ancestor in node.ancestors() {
+                if !keep.insert(ancestor.key()) {
+                    break;
                 }
-            })
-            .for_each(|_| {});
+            }
+        });
+
+        keep
+    }
+
+    // Shows only the nodes matching `filter` together with their ancestors,
+    // which get expanded. Ancestors that were collapsed before are collapsed
+    // again once the filter changes or is cleared
+    pub fn set_filter(&self, filter: Option<TreeFilter>) {
+        let mut collapsed = IndexMap::new();
+        for key in std::mem::take(&mut *self.auto_expanded.borrow_mut()) {
+            if let Some(node) = self.find(key) {
+                let mut flags = node.flags();
+                flags.remove(TreeFlags::EXPANDED);
+                node.set_flags(flags);
+                collapsed.insert(key, node);
+            }
+        }
+
+        let mut expanded = vec![];
+        if let Some(filter) = &filter {
+            let mut auto_expanded = BTreeSet::new();
+            self.root.walk(&mut |node| {
+                if !filter(&*node.data()) {
+                    return;
+                }
+
+                for ancestor in node.ancestors() {
+                    let mut flags = ancestor.flags();
+                    if !flags.contains(TreeFlags::EXPANDED) {
+                        // its children are already there, so it counts as loaded
+                        flags.insert(TreeFlags::EXPANDED | TreeFlags::READY);
+                        ancestor.set_flags(flags);
+                        auto_expanded.insert(ancestor.key());
+
+                        // expanded by the previous filter as well
+                        if collapsed.shift_remove(&ancestor.key()).is_none() {
+                            expanded.push(ancestor);
+                        }
+                    }
+                }
+            });
+
+            self.auto_expanded.replace(auto_expanded);
+        }
+
+        self.filter.replace(filter);
+        self.flatten();
+        self.notify_update_all();
+        self.notify_expansion(expanded, collapsed.into_values().collect());
+    }
+
+    pub fn has_filter(&self) -> bool {
+        self.filter.borrow().is_some()
+    }
+
+    // Splices the rows of `node` into the list after only its subtree
+    // changed, so the rest of the list isn't rebuilt and subscribers get the
+    // replaced rows instead of `update_all`
+    fn flatten_subtree(&self, node: &Rc<TreeNode>) {
+        // a filter can hide the node or the rows around it
+        let index = match self.index_of(node.key()) {
+            Some(index) if self.filter.borrow().is_none() => index,
+            _ => {
+                self.flatten();
+                return self.notify_update_all();
+            }
+        };
+
+        let removed = self.subtree_rows(node, index);
+        // starts with the node itself, which stays in place
+        let rows = node.flatten();
+        let inserted = rows.len() - 1;
+
+        let mut flat = self.flat.borrow_mut();
+        let tail = flat.split_off(index + 1 + removed);
+        flat.truncate(index + 1);
+        flat.extend(rows.into_iter().skip(1));
+        flat.extend(tail);
+        drop(flat);
+
+        self.total_height.set(None);
+
+        let start = index + 1;
+        let change = TreeChange {
+            removed: start..start + removed,
+            inserted: start..start + inserted,
+        };
+
+        self.for_each_subscriber(|c| c.update_rows(&change))
+    }
+
+    // Visible rows below `node` at `index` that belong to its subtree
+    fn subtree_rows(&self, node: &Rc<TreeNode>, index: usize) -> usize {
+        self.flat
+            .borrow()
+            .values()
+            .skip(index + 1)
+            .take_while(|row| row.is_within(node))
+            .count()
+    }
+
+    pub fn clear(&self) {
+        self.root.clear_children();
+        self.loads.borrow_mut().clear();
+        self.pending_expansion.borrow_mut().clear();
+        self.pending_selection.borrow_mut().clear();
+        self.flat.borrow_mut().clear();
+        self.total_height.set(None);
+        self.notify_update_all();
+    }
+
+    // The sum is cached until the visible list changes, so `height_of` is
+    // expected to return the same heights between calls
+    pub fn total_visible_height(&self, height_of: &dyn Fn(&dyn TreeItem) -> usize) -> usize {
+        if let Some(height) = self.total_height.get() {
+            return height;
+        }
+
+        let height = self
+            .flat
+            .borrow()
+            .values()
+            .map(|node| height_of(&**node))
+            .sum();
+
+        self.total_height.set(Some(height));
+        height
+    }
+
+    // A frozen tree ignores clicks, expansion and structural changes until
+    // it is unfrozen again
+    pub fn set_frozen(&self, frozen: bool) {
+        if self.frozen.replace(frozen) != frozen {
+            self.notify_update_all();
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.get()
+    }
+
+    pub fn set_expand_on(&self, expand_on: ExpandOn) {
+        self.expand_on.set(expand_on);
+    }
+
+    // Like `add_subscriber`, but the subscriber is detached as soon as the
+    // returned handle is dropped, even if it is still alive
+    pub fn subscribe(&self, subscriber: Rc<dyn TreeSubscriber>) -> Subscription {
+        let handle = Subscription {
+            tree: self.this.clone(),
+            subscriber: Rc::downgrade(&subscriber),
+        };
+
+        self.add_subscriber(subscriber);
+        handle
+    }
+
+    // Subscribers are notified from a snapshot, so they can subscribe or
+    // unsubscribe while being notified. On targets that unwind, a panicking
+    // subscriber doesn't keep the rest from being notified and the first
+    // panic is resumed afterwards. wasm32 aborts on the first panic instead
+    fn for_each_subscriber(&self, func: impl Fn(&dyn TreeSubscriber)) {
+        let subscribers = {
+            let mut subscribers = self.subscribers.borrow_mut();
+            subscribers.retain(|c| c.strong_count() > 0);
+            subscribers
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect::<Vec<_>>()
+        };
+
+        let mut first_panic = None;
+        for c in subscribers {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| func(&*c)));
+            if let Err(payload) = result {
+                first_panic.get_or_insert(payload);
+            }
+        }
+
+        if let Some(payload) = first_panic {
+            panic::resume_unwind(payload);
+        }
     }
 
     fn notify_update_all(&self) {
         self.for_each_subscriber(|c| c.update_all())
     }
 
+    // Item updates are batched until the next animation frame unless sync
+    // updates are enabled
+    pub fn set_sync_updates(&self, sync: bool) {
+        self.sync_updates.set(sync);
+    }
+
+    // Lets loads run outside of the browser, e.g. on a local executor
+    pub fn set_spawner(&self, spawner: Spawner) {
+        self.spawner.replace(spawner);
+    }
+
+    fn spawn(&self, future: LocalBoxFuture<'static, ()>) {
+        let spawner = self.spawner.borrow().clone();
+        spawner(future)
+    }
+
     fn notify_update_item(&self, key: KeyType) {
-        self.for_each_subscriber(|c| c.update_item(key))
+        if self.sync_updates.get() {
+            return self.for_each_subscriber(|c| c.update_item(key));
+        }
+
+        if self.queue_update_item(key) {
+            self.schedule_flush();
+        }
     }
 
-    fn get_item(&self, key: usize) -> Rc<TreeNode> {
-        if self.root.key() == key {
-            self.root.clone()
-        } else {
-            let flat = self.flat.borrow();
-            let item = flat.get(&key).unwrap();
-            item.clone()
+    // Returns `true` for the first key of a batch, which is when a flush
+    // has to be scheduled
+    fn queue_update_item(&self, key: KeyType) -> bool {
+        let mut pending = self.pending_updates.borrow_mut();
+        let first = pending.is_empty();
+        pending.insert(key);
+        first
+    }
+
+    fn schedule_flush(&self) {
+        let this = self.this.clone();
+        let flush = Closure::once_into_js(move || {
+            if let Some(this) = this.upgrade() {
+                this.flush_updates();
+            }
+        });
+
+        web_sys::window()
+            .unwrap()
+            .request_animation_frame(flush.unchecked_ref())
+            .unwrap();
+    }
+
+    fn flush_updates(&self) {
+        let keys = std::mem::take(&mut *self.pending_updates.borrow_mut());
+        if keys.is_empty() {
+            return;
+        }
+
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        self.for_each_subscriber(|c| c.update_items(&keys))
+    }
+
+    // Rows of a stale list are looked up in the nodes, the same as `count`
+    fn node_at(&self, index: usize) -> Option<Rc<TreeNode>> {
+        if self.is_flat_stale() {
+            return self
+                .root
+                .find_by_index(index)
+                .pop()
+                .map(|cursor| cursor.node);
+        }
+
+        self.flat
+            .borrow()
+            .get_index(index)
+            .map(|(_, node)| node.clone())
+    }
+
+    // The nodes changed since the last `flatten`, e.g. a node was expanded
+    // directly. Any change clears the cached rows of the root, which the
+    // list was built from. A filtered list can only be rebuilt by `flatten`
+    fn is_flat_stale(&self) -> bool {
+        !self.has_filter() && !self.root.has_flat_cache()
+    }
+
+    // Row of a visible node, the inverse of `item`. `None` for nodes inside
+    // collapsed folders or hidden by the filter
+    pub fn index_of(&self, key: KeyType) -> Option<usize> {
+        self.flat.borrow().get_index_of(&key)
+    }
+
+    // Unlike the visible list, covers collapsed and scrolled out nodes too
+    pub fn find(&self, key: KeyType) -> Option<Rc<TreeNode>> {
+        // placeholder rows stand for no node
+        if PlaceholderData::is_key(key) {
+            return None;
+        }
+
+        if let Some(node) = self.flat.borrow().get(&key) {
+            return Some(node.clone());
+        }
+
+        self.root.find(key)
+    }
+
+    // Titles from the top level down to the node, e.g. for a "copy path"
+    // action. The hidden root isn't part of any path, so it has none
+    pub fn path_string(&self, key: KeyType, separator: &str) -> Option<String> {
+        let node = self.find(key)?;
+        if node.is_root() {
+            return None;
+        }
+
+        let mut path = String::new();
+        for ancestor in node.ancestors().iter().rev() {
+            path.push_str(&ancestor.title());
+            path.push_str(separator);
+        }
+
+        path.push_str(&node.title());
+        Some(path)
+    }
+
+    pub fn insert_children(&self, parent_key: KeyType, children: Vec<Rc<TreeNode>>) {
+        if self.is_frozen() {
+            tracing::debug!("Tree is frozen, ignoring insert into {}", parent_key);
+            return;
+        }
+
+        let Some(parent) = self.find(parent_key) else {
+            tracing::warn!("Cannot insert children into unknown node {}", parent_key);
+            return;
+        };
+
+        parent.insert(children);
+
+        // an asynchronously loaded root is done once its children arrive
+        if parent.is_root() && parent.flags().contains(TreeFlags::LOADING) {
+            let mut flags = parent.flags();
+            flags.remove(TreeFlags::LOADING);
+            flags.insert(TreeFlags::EXPANDED | TreeFlags::READY);
+            parent.set_flags(flags);
+        }
+
+        let visible = parent.is_root() || self.flat.borrow().contains_key(&parent_key);
+        if visible && parent.flags().contains(TreeFlags::EXPANDED) {
+            self.flatten();
+            self.notify_update_all();
+        }
+    }
+
+    // Removes the node with its subtree, the removed nodes are dropped from
+    // the selection as well
+    pub fn remove(&self, key: KeyType) -> Option<Rc<TreeNode>> {
+        if self.is_frozen() {
+            tracing::debug!("Tree is frozen, ignoring removal of {}", key);
+            return None;
+        }
+
+        let node = self.find(key).filter(|node| !node.is_root())?;
+        let parent = node.parent()?;
+        parent.remove_child(key)?;
+
+        let mut removed = BTreeSet::from([key]);
+        node.walk(&mut |child| {
+            removed.insert(child.key());
+        });
+
+        let selection = self
+            .selection
+            .borrow()
+            .difference(&removed)
+            .copied()
+            .collect();
+        self.set_selection(selection);
+        self.pending_expansion
+            .borrow_mut()
+            .retain(|key| !removed.contains(key));
+        self.pending_selection
+            .borrow_mut()
+            .retain(|key| !removed.contains(key));
+
+        self.flatten();
+        self.notify_update_all();
+        Some(node)
+    }
+
+    pub fn move_node(
+        &self,
+        key: KeyType,
+        new_parent: KeyType,
+        index: usize,
+    ) -> Result<(), MoveError> {
+        if self.is_frozen() {
+            return Err(MoveError::Frozen);
+        }
+
+        let node = self
+            .find(key)
+            .filter(|node| !node.is_root())
+            .ok_or(MoveError::UnknownNode)?;
+        let new_parent = self.find(new_parent).ok_or(MoveError::UnknownParent)?;
+
+        if new_parent.is_within(&node) {
+            return Err(MoveError::IntoDescendant);
+        }
+
+        node.move_to(&new_parent, index);
+
+        self.flatten();
+        self.notify_update_all();
+        Ok(())
+    }
+
+    // Moves the node next to or into the node it was dropped on
+    pub fn drop_node(&self, key: KeyType, target: DropTarget) -> Result<(), MoveError> {
+        let (parent, index) = self.drop_destination(key, target)?;
+        self.move_node(key, parent, index)
+    }
+
+    // Parent and index for `move_node`
+    fn drop_destination(
+        &self,
+        key: KeyType,
+        target: DropTarget,
+    ) -> Result<(KeyType, usize), MoveError> {
+        let node = self
+            .find(key)
+            .filter(|node| !node.is_root())
+            .ok_or(MoveError::UnknownNode)?;
+        let over = self.find(target.key).ok_or(MoveError::UnknownParent)?;
+
+        if over.is_within(&node) {
+            return Err(MoveError::IntoDescendant);
         }
+
+        let folder = over.expandable();
+        let loaded = over.flags().contains(TreeFlags::READY);
+        let (parent, index) = match target.position {
+            DropPosition::Inside if !folder => return Err(MoveError::NotAFolder),
+            DropPosition::Inside if !loaded => return Err(MoveError::NotLoaded),
+            DropPosition::Inside => return Ok((over.key(), usize::MAX)),
+            // the children of an expanded folder show right below it
+            DropPosition::After if over.expanded() && over.first_child().is_some() => {
+                return Ok((over.key(), 0))
+            }
+            DropPosition::Before | DropPosition::After => {
+                let parent = over.parent().ok_or(MoveError::UnknownParent)?;
+                let index = parent
+                    .inner()
+                    .children
+                    .get_index_of(&over.key())
+                    .ok_or(MoveError::UnknownParent)?;
+                let after = target.position == DropPosition::After;
+                (parent, index + after as usize)
+            }
+        };
+
+        // the node leaves its old place before it's inserted
+        let current = node
+            .parent()
+            .filter(|current| Rc::ptr_eq(current, &parent))
+            .and_then(|parent| parent.inner().children.get_index_of(&key));
+        let index = match current {
+            Some(current) if current < index => index - 1,
+            _ => index,
+        };
+
+        Ok((parent.key(), index))
     }
 
     pub fn expand(&self, key: KeyType) {
-        let item = self.get_item(key);
+        if self.is_frozen() {
+            tracing::debug!("Tree is frozen, ignoring expand of {}", key);
+            return;
+        }
+
+        let Some(item) = self.find(key) else {
+            return;
+        };
+
         let mut flags = item.flags();
 
         if !flags.contains(TreeFlags::EXPANDABLE) {
             return;
         }
 
+        if flags.contains(TreeFlags::LOADING) {
+            // Collapse before the children arrived
+            self.cancel_load(&item);
+            self.flatten_subtree(&item);
+            return;
+        }
+
         if flags.contains(TreeFlags::EXPANDED) {
             // Collapse
             flags.remove(TreeFlags::EXPANDED);
             item.set_flags(flags);
-            self.flat.replace(self.root.flatten());
-            self.notify_update_all();
+            self.on_collapse(item.clone());
+            self.flatten_subtree(&item);
             return;
         }
 
@@ -109,79 +763,2381 @@ impl DynamicTree {
             // Just expand
             flags.insert(TreeFlags::EXPANDED);
             item.set_flags(flags);
-            self.flat.replace(self.root.flatten());
-            self.notify_update_all();
+            self.on_expand(item.clone());
+            self.flatten_subtree(&item);
 
             return;
         }
 
-        match self.provider.expand(&item) {
-            TreeExpandResult::Ready => {
-                let mut flags = item.flags();
+        // an async load shows the placeholder row until it's done
+        self.load(item.clone());
+        self.flatten_subtree(&item);
+    }
 
-                flags.insert(TreeFlags::EXPANDED);
-                flags.insert(TreeFlags::READY);
-                item.set_flags(flags);
+    // Asks the provider for the children, the caller re-flattens the list
+    fn load(&self, item: Rc<TreeNode>) {
+        let Some(job) = self.provider.expand(&item).into_job() else {
+            let mut flags = item.flags();
+
+            flags.insert(TreeFlags::EXPANDED);
+            flags.insert(TreeFlags::READY);
+            item.set_flags(flags);
+            self.on_expand(item.clone());
+            let restored = self.expand_pending(&item);
+            self.notify_expansion(restored, vec![]);
+            return;
+        };
 
-                self.flat.replace(self.root.flatten());
-                self.notify_update_all();
+        let generation = self.begin_load(&item);
+        let this = self.this.clone();
+
+        self.spawn(
+            async move {
+                let result = job.await;
+                if let Some(this) = this.upgrade() {
+                    this.finish_load(item, generation, result);
+                }
             }
-            TreeExpandResult::Async(job) => {
-                let mut flags = item.flags();
+            .boxed_local(),
+        )
+    }
 
-                flags.toggle(TreeFlags::LOADING);
+    fn begin_load(&self, item: &Rc<TreeNode>) -> usize {
+        let generation = self.load_generation.get() + 1;
+        self.load_generation.set(generation);
+        self.loads.borrow_mut().insert(item.key(), generation);
+        let mut flags = item.flags();
+        flags.remove(TreeFlags::FAILED);
+        flags.insert(TreeFlags::LOADING);
+        item.set_flags(flags);
+        generation
+    }
 
-                item.set_flags(flags);
-                self.notify_update_item(key);
-                let this = self.this.clone();
+    // The load keeps running, its result is dropped once it arrives
+    fn cancel_load(&self, item: &Rc<TreeNode>) {
+        self.loads.borrow_mut().remove(&item.key());
+        self.settle_pending();
 
-                wasm_bindgen_futures::spawn_local(async move {
-                    let result = job.await.unwrap_throw();
+        let mut flags = item.flags();
+        flags.remove(TreeFlags::LOADING);
+        item.set_flags(flags);
+        item.set_load_progress(None);
+    }
 
-                    flags.remove(TreeFlags::LOADING);
-                    flags.insert(TreeFlags::EXPANDED);
-                    flags.insert(TreeFlags::READY);
-                    item.set_flags(flags);
+    fn finish_load(
+        &self,
+        item: Rc<TreeNode>,
+        generation: usize,
+        result: Result<Vec<Rc<TreeNode>>, ProviderError>,
+    ) {
+        let key = item.key();
+        if self.loads.borrow().get(&key) != Some(&generation) {
+            tracing::debug!("Dropping a stale load of {}", key);
+            return;
+        }
 
-                    item.insert(result);
+        self.loads.borrow_mut().remove(&key);
+        self.finish_expand(item, result);
+    }
 
-                    if let Some(this) = this.upgrade() {
-                        this.flat.replace(this.root.flatten());
-                        tracing::info!("{:?}", this.root.flatten());
-                        this.notify_update_all();
-                        tracing::info!("Expanded");
-                    }
-                })
+    // Expanded keys in depth-first order. Ancestors opened only to show
+    // filter matches aren't included
+    pub fn expansion_snapshot(&self) -> Vec<KeyType> {
+        let auto_expanded = self.auto_expanded.borrow();
+        let mut keys = vec![];
+        self.root.walk(&mut |node| {
+            let key = node.key();
+            if node.flags().contains(TreeFlags::EXPANDED) && !auto_expanded.contains(&key) {
+                keys.push(key);
             }
+        });
+
+        keys
+    }
+
+    // Expands the given keys, loading their children where needed. Keys that
+    // aren't in the tree are kept while loads are in flight: a folder inside
+    // a lazy subtree opens once its parent has loaded, keys still missing
+    // when the last load is done are dropped. Folders that aren't listed are
+    // left as they are
+    pub fn restore_expansion(&self, keys: &[KeyType]) {
+        if self.is_frozen() {
+            return;
         }
+
+        self.pending_expansion
+            .replace(keys.iter().copied().collect());
+        let expanded = self.expand_pending(&self.root);
+        self.settle_pending();
+        self.flatten();
+        self.notify_update_all();
+        self.notify_expansion(expanded, vec![]);
     }
 
-    fn on_click(&self, item: Rc<TreeNode>) {
-        if let Some(on_click) = self.callbacks.on_click.as_ref() {
-            on_click(item)
+    // Returns the loaded folders it expanded, the ones it starts loading
+    // report themselves once their children are in
+    fn expand_pending(&self, parent: &Rc<TreeNode>) -> Vec<Rc<TreeNode>> {
+        if self.pending_expansion.borrow().is_empty() {
+            return vec![];
         }
+
+        let mut expanded = vec![];
+        let mut unloaded = vec![];
+        parent.walk(&mut |node| {
+            if !self.pending_expansion.borrow_mut().remove(&node.key()) {
+                return;
+            }
+
+            let mut flags = node.flags();
+            if !flags.contains(TreeFlags::EXPANDABLE)
+                || flags.intersects(TreeFlags::EXPANDED | TreeFlags::LOADING)
+            {
+                return;
+            }
+
+            if flags.contains(TreeFlags::READY) {
+                flags.insert(TreeFlags::EXPANDED);
+                node.set_flags(flags);
+                expanded.push(node.clone());
+            } else {
+                unloaded.push(node.clone());
+            }
+        });
+
+        for node in unloaded {
+            self.load(node);
+        }
+
+        expanded
     }
-}
 
-impl TreeController for DynamicTree {
-    fn item(&self, index: usize) -> Rc<dyn TreeItem> {
-        self.flat.borrow().get_index(index).unwrap().1.clone()
+    // Only a load can still bring in the missing keys
+    fn settle_pending(&self) {
+        if self.loads.borrow().is_empty() {
+            self.pending_expansion.borrow_mut().clear();
+            self.pending_selection.borrow_mut().clear();
+        }
     }
 
-    fn handle_click(&self, key: KeyType) {
-        self.expand(key);
+    fn select_pending(&self, parent: &Rc<TreeNode>) {
+        if self.pending_selection.borrow().is_empty() {
+            return;
+        }
 
-        let item = self.get_item(key);
-        self.on_click(item);
+        let mut arrived = vec![];
+        parent.walk(&mut |node| {
+            if self.pending_selection.borrow_mut().remove(&node.key()) {
+                arrived.push(node.key());
+            }
+        });
+
+        if arrived.is_empty() {
+            return;
+        }
+
+        if self.anchor.get().is_none() {
+            self.anchor.set(arrived.last().copied());
+        }
+
+        let mut selection = self.selection.borrow().clone();
+        selection.extend(arrived);
+        self.set_selection(selection);
     }
 
-    fn count(&self) -> usize {
-        self.flat.borrow().len()
+    pub fn snapshot(&self) -> TreeSnapshot {
+        TreeSnapshot {
+            expanded: self.expansion_snapshot(),
+            selected: self.selected_keys(),
+        }
     }
 
-    fn add_subscriber(&self, subscriber: Rc<dyn TreeSubscriber>) {
-        self.subscribers
-            .borrow_mut()
-            .push(Rc::downgrade(&subscriber))
+    // Selected keys that are no longer in the tree are dropped, unless a
+    // restored folder is still loading and may bring them in
+    pub fn restore(&self, snapshot: &TreeSnapshot) {
+        self.restore_expansion(&snapshot.expanded);
+
+        let (selection, missing): (BTreeSet<_>, BTreeSet<_>) = snapshot
+            .selected
+            .iter()
+            .copied()
+            .partition(|key| self.find(*key).is_some());
+
+        if !self.loads.borrow().is_empty() {
+            self.pending_selection.replace(missing);
+        }
+
+        self.anchor.set(selection.last().copied());
+        self.set_selection(selection);
+    }
+
+    // Providers report progress of async loads here, `None` removes the
+    // progress bar
+    pub fn set_load_progress(&self, key: KeyType, progress: Option<f32>) {
+        let Some(node) = self.find(key) else {
+            return;
+        };
+
+        if node.load_progress() != progress {
+            node.set_load_progress(progress);
+            self.notify_update_item(key);
+        }
+    }
+
+    // Highlights parts of the title, `None` renders it as plain text
+    pub fn set_title_ranges(&self, key: KeyType, ranges: Option<Vec<Range<usize>>>) {
+        let Some(node) = self.find(key) else {
+            return;
+        };
+
+        if node.title_ranges() != ranges {
+            node.set_title_ranges(ranges);
+            self.notify_update_item(key);
+        }
+    }
+
+    fn finish_expand(&self, item: Rc<TreeNode>, result: Result<Vec<Rc<TreeNode>>, ProviderError>) {
+        let mut flags = item.flags();
+        flags.remove(TreeFlags::LOADING);
+        item.set_load_progress(None);
+
+        match result {
+            Ok(children) => {
+                flags.remove(TreeFlags::FAILED);
+                flags.insert(TreeFlags::EXPANDED);
+                flags.insert(TreeFlags::READY);
+                item.set_flags(flags);
+
+                item.insert(children);
+
+                // a checked node stays checked with everything it loads
+                if item.check_state() == CheckState::Checked {
+                    item.walk(&mut |child| {
+                        set_check_state(child, CheckState::Checked);
+                    });
+                }
+
+                self.on_expand(item.clone());
+                let restored = self.expand_pending(&item);
+                self.flatten_subtree(&item);
+                self.notify_expansion(restored, vec![]);
+                self.select_pending(&item);
+                tracing::info!("Expanded");
+            }
+            Err(err) => {
+                // without `READY` the next expand asks the provider again
+                flags.insert(TreeFlags::FAILED);
+                item.set_flags(flags);
+
+                tracing::warn!("Failed to expand node {}", item.key());
+                self.on_expand_error(item.clone(), err);
+
+                // removes the placeholder row
+                self.flatten_subtree(&item);
+            }
+        }
+
+        self.settle_pending();
+    }
+
+    // Only expands nodes that are already loaded, lazy subtrees that were
+    // never opened stay collapsed instead of triggering a provider call each.
+    // The list is re-flattened and subscribers are notified once
+    pub fn expand_all(&self) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let mut expanded = vec![];
+        self.root.walk(&mut |node| {
+            let mut flags = node.flags();
+            if flags.contains(TreeFlags::EXPANDABLE | TreeFlags::READY)
+                && !flags.contains(TreeFlags::EXPANDED)
+            {
+                flags.insert(TreeFlags::EXPANDED);
+                node.set_flags(flags);
+                expanded.push(node.clone());
+            }
+        });
+
+        self.flatten();
+        self.notify_update_all();
+        self.notify_expansion(expanded, vec![]);
+    }
+
+    // Expands every collapsed ancestor of `key` so it shows up in the
+    // visible list, returns `false` if there is no such node or the tree is
+    // frozen
+    pub fn reveal(&self, key: KeyType) -> bool {
+        if self.is_frozen() {
+            return false;
+        }
+
+        let Some(node) = self.find(key) else {
+            return false;
+        };
+
+        let mut expanded = vec![];
+        let mut parent = node.parent();
+        while let Some(current) = parent {
+            if current.is_root() {
+                break;
+            }
+
+            let mut flags = current.flags();
+            if !flags.contains(TreeFlags::EXPANDED) {
+                // its children are already there, so it counts as loaded
+                flags.insert(TreeFlags::EXPANDED | TreeFlags::READY);
+                current.set_flags(flags);
+                expanded.push(current.clone());
+            }
+
+            parent = current.parent();
+        }
+
+        if !expanded.is_empty() {
+            self.flatten();
+            self.notify_update_all();
+            // outermost first, like expanding them one by one
+            expanded.reverse();
+            self.notify_expansion(expanded, vec![]);
+        }
+
+        true
+    }
+
+    // Loaded children are kept, so expanding again doesn't hit the provider
+    pub fn collapse_all(&self) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let mut collapsed = vec![];
+        self.root.walk(&mut |node| {
+            let mut flags = node.flags();
+            if flags.contains(TreeFlags::EXPANDED) {
+                flags.remove(TreeFlags::EXPANDED);
+                node.set_flags(flags);
+                collapsed.push(node.clone());
+            }
+        });
+
+        self.flatten();
+        self.notify_update_all();
+        self.notify_expansion(vec![], collapsed);
+    }
+
+    fn resolve_display(&self, node: &Rc<TreeNode>) {
+        let mut flags = node.flags();
+        flags.remove(TreeFlags::UNRESOLVED);
+        node.set_flags(flags);
+
+        let Some(job) = self.provider.resolve_display(node) else {
+            return;
+        };
+
+        let this = self.this.clone();
+        let node = node.clone();
+
+        self.spawn(
+            async move {
+                if let Ok(data) = job.await {
+                    if let Some(this) = this.upgrade() {
+                        this.apply_display(&node, data);
+                    }
+                }
+            }
+            .boxed_local(),
+        )
+    }
+
+    fn apply_display(&self, node: &Rc<TreeNode>, data: Box<dyn TreeData>) {
+        node.set_data(data);
+        self.notify_update_item(node.key());
+    }
+
+    // Returns `true` if the node existed and its data actually changed
+    pub fn set_data_if_changed(&self, key: KeyType, data: Box<dyn TreeData>) -> bool {
+        let Some(node) = self.find(key) else {
+            return false;
+        };
+
+        let changed = node.set_data_if_changed(data);
+        if changed {
+            self.notify_update_item(key);
+        }
+
+        changed
+    }
+
+    // Checking a node checks its whole subtree, ancestors become checked
+    // once all their children are and indeterminate while only some are
+    pub fn set_checked(&self, key: KeyType, checked: bool) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let Some(node) = self.find(key).filter(|node| !node.is_root()) else {
+            return;
+        };
+
+        let mut changed = Vec::new();
+        let state = if checked {
+            CheckState::Checked
+        } else {
+            CheckState::Unchecked
+        };
+
+        if set_check_state(&node, state) {
+            changed.push(key);
+        }
+
+        node.walk(&mut |child| {
+            if set_check_state(child, state) {
+                changed.push(child.key());
+            }
+        });
+
+        for ancestor in node.ancestors() {
+            let state = ancestor
+                .inner()
+                .children
+                .values()
+                .map(|child| child.check_state())
+                .reduce(|a, b| if a == b { a } else { CheckState::Indeterminate })
+                .unwrap_or(state);
+
+            if !set_check_state(&ancestor, state) {
+                break;
+            }
+
+            changed.push(ancestor.key());
+        }
+
+        for key in changed {
+            self.notify_update_item(key);
+        }
+    }
+
+    // An indeterminate node becomes checked
+    pub fn toggle_checked(&self, key: KeyType) {
+        if let Some(node) = self.find(key) {
+            self.set_checked(key, node.check_state() != CheckState::Checked);
+        }
+    }
+
+    // Includes the checked ancestors, not only leaves
+    pub fn checked_keys(&self) -> Vec<KeyType> {
+        let mut keys = Vec::new();
+        self.root.walk(&mut |node| {
+            if node.check_state() == CheckState::Checked {
+                keys.push(node.key());
+            }
+        });
+
+        keys
+    }
+
+    pub fn selected_keys(&self) -> Vec<KeyType> {
+        self.selection.borrow().iter().copied().collect()
+    }
+
+    pub fn is_selected(&self, key: KeyType) -> bool {
+        self.selection.borrow().contains(&key)
+    }
+
+    pub fn select(&self, key: KeyType) {
+        if PlaceholderData::is_key(key) {
+            return;
+        }
+
+        self.pending_selection.borrow_mut().clear();
+        self.anchor.set(Some(key));
+        self.set_selection(BTreeSet::from([key]));
+    }
+
+    pub fn toggle_selected(&self, key: KeyType) {
+        if PlaceholderData::is_key(key) {
+            return;
+        }
+
+        let mut selection = self.selection.borrow().clone();
+        if !selection.remove(&key) {
+            selection.insert(key);
+        }
+
+        self.pending_selection.borrow_mut().clear();
+        self.anchor.set(Some(key));
+        self.set_selection(selection);
+    }
+
+    // Selects the rows between the anchor and `key` in visible order, the
+    // anchor stays in place so the range can be adjusted with more clicks
+    pub fn select_range(&self, key: KeyType) {
+        if PlaceholderData::is_key(key) {
+            return;
+        }
+
+        let flat = self.flat.borrow();
+        let Some(end) = flat.get_index_of(&key) else {
+            return;
+        };
+
+        let start = self
+            .anchor
+            .get()
+            .and_then(|anchor| flat.get_index_of(&anchor))
+            .unwrap_or(end);
+
+        let selection = (start.min(end)..=start.max(end))
+            .map(|index| *flat.get_index(index).unwrap().0)
+            .filter(|key| !PlaceholderData::is_key(*key))
+            .collect();
+
+        drop(flat);
+
+        if self.anchor.get().is_none() {
+            self.anchor.set(Some(key));
+        }
+
+        self.pending_selection.borrow_mut().clear();
+        self.set_selection(selection);
+    }
+
+    pub fn clear_selection(&self) {
+        self.pending_selection.borrow_mut().clear();
+        self.anchor.set(None);
+        self.set_selection(BTreeSet::new());
+    }
+
+    // Every selection mutation goes through here, so `on_selection_change`
+    // fires exactly once per actual change
+    fn set_selection(&self, selection: BTreeSet<KeyType>) {
+        let change = SelectionChange::diff(&self.selection.borrow(), &selection);
+        let Some(change) = change else {
+            return;
+        };
+
+        self.selection.replace(selection);
+
+        for key in change.added.iter().chain(&change.removed) {
+            self.notify_update_item(*key);
+        }
+
+        if let Some(on_selection_change) = self.callbacks.on_selection_change.as_ref() {
+            on_selection_change(change)
+        }
+    }
+
+    fn on_click(&self, item: Rc<TreeNode>) {
+        if let Some(on_click) = self.callbacks.on_click.as_ref() {
+            on_click(item)
+        }
+    }
+
+    fn on_dbl_click(&self, item: Rc<TreeNode>) {
+        if let Some(on_dbl_click) = self.callbacks.on_dbl_click.as_ref() {
+            on_dbl_click(item)
+        }
+    }
+
+    fn on_context_menu(&self, item: Rc<TreeNode>, x: i32, y: i32) {
+        if let Some(on_context_menu) = self.callbacks.on_context_menu.as_ref() {
+            on_context_menu(item, x, y)
+        }
+    }
+
+    fn on_rename(&self, item: Rc<TreeNode>, title: String) {
+        if let Some(on_rename) = self.callbacks.on_rename.as_ref() {
+            on_rename(item, title)
+        }
+    }
+
+    fn on_drop(&self, item: Rc<TreeNode>, target: DropTarget) {
+        if let Some(on_drop) = self.callbacks.on_drop.as_ref() {
+            on_drop(item, target)
+        }
+    }
+
+    fn on_hover(&self, item: Option<Rc<TreeNode>>) {
+        if let Some(on_hover) = self.callbacks.on_hover.as_ref() {
+            on_hover(item)
+        }
+    }
+
+    fn on_expand(&self, item: Rc<TreeNode>) {
+        if let Some(on_expand) = self.callbacks.on_expand.as_ref() {
+            on_expand(item)
+        }
+    }
+
+    fn on_collapse(&self, item: Rc<TreeNode>) {
+        if let Some(on_collapse) = self.callbacks.on_collapse.as_ref() {
+            on_collapse(item)
+        }
+    }
+
+    // Batch operations report every node they toggled once the list is up
+    // to date, so the callbacks can read it
+    fn notify_expansion(&self, expanded: Vec<Rc<TreeNode>>, collapsed: Vec<Rc<TreeNode>>) {
+        for node in collapsed {
+            self.on_collapse(node);
+        }
+
+        for node in expanded {
+            self.on_expand(node);
+        }
+    }
+
+    fn on_expand_error(&self, item: Rc<TreeNode>, err: ProviderError) {
+        if let Some(on_expand_error) = self.callbacks.on_expand_error.as_ref() {
+            on_expand_error(item, err)
+        }
+    }
+}
+
+// Returns `false` if the node already was in that state
+fn set_check_state(node: &TreeNode, state: CheckState) -> bool {
+    if node.check_state() == state {
+        return false;
+    }
+
+    let mut flags = node.flags();
+    flags.remove(TreeFlags::CHECKED | TreeFlags::INDETERMINATE);
+    match state {
+        CheckState::Checked => flags.insert(TreeFlags::CHECKED),
+        CheckState::Indeterminate => flags.insert(TreeFlags::INDETERMINATE),
+        CheckState::Unchecked => {}
+    }
+
+    node.set_flags(flags);
+    true
+}
+
+impl TreeController for DynamicTree {
+    fn item(&self, index: usize) -> Rc<dyn TreeItem> {
+        let node = self.node_at(index).unwrap();
+        if node.flags().contains(TreeFlags::UNRESOLVED) {
+            self.resolve_display(&node);
+        }
+
+        node
+    }
+
+    // Clicks may land on a row that was removed before the next paint
+    fn handle_click(&self, key: KeyType, modifiers: ClickModifiers) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let Some(item) = self.find(key) else {
+            return;
+        };
+
+        // modified clicks only change the selection
+        if modifiers.range {
+            return self.select_range(key);
+        } else if modifiers.toggle {
+            return self.toggle_selected(key);
+        }
+
+        self.select(key);
+        if self.expand_on.get() == ExpandOn::Click {
+            self.expand(key);
+        }
+
+        self.on_click(item);
+    }
+
+    fn handle_dbl_click(&self, key: KeyType) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let Some(item) = self.find(key) else {
+            return;
+        };
+
+        if self.expand_on.get() == ExpandOn::DoubleClick {
+            self.expand(key);
+        }
+
+        self.on_dbl_click(item);
+    }
+
+    fn handle_context_menu(&self, key: KeyType, x: i32, y: i32) {
+        let Some(item) = self.find(key) else {
+            return;
+        };
+
+        self.on_context_menu(item, x, y);
+    }
+
+    fn handle_rename(&self, key: KeyType, title: String) {
+        let Some(item) = self.find(key) else {
+            return;
+        };
+
+        if *item.title() != title {
+            self.on_rename(item, title);
+        }
+    }
+
+    fn row_height(&self, index: usize) -> Option<usize> {
+        self.flat.borrow().get_index(index)?.1.height()
+    }
+
+    fn handle_hover(&self, key: Option<KeyType>) {
+        self.on_hover(key.and_then(|key| self.find(key)))
+    }
+
+    fn can_drop(&self, key: KeyType, target: DropTarget) -> bool {
+        !self.is_frozen() && self.drop_destination(key, target).is_ok()
+    }
+
+    fn handle_drop(&self, key: KeyType, target: DropTarget) {
+        if let Err(err) = self.drop_node(key, target) {
+            tracing::debug!("Ignoring drop of {}: {:?}", key, err);
+            return;
+        }
+
+        if let Some(node) = self.find(key) {
+            self.on_drop(node, target);
+        }
+    }
+
+    // Counted from the nodes while the list is stale, so it is right even
+    // before the list is re-flattened
+    fn count(&self) -> usize {
+        if self.is_flat_stale() {
+            return self.root.visible_len();
+        }
+
+        self.flat.borrow().len()
+    }
+
+    fn add_subscriber(&self, subscriber: Rc<dyn TreeSubscriber>) {
+        let subscriber = Rc::downgrade(&subscriber);
+        let mut subscribers = self.subscribers.borrow_mut();
+        if !subscribers.iter().any(|c| c.ptr_eq(&subscriber)) {
+            subscribers.push(subscriber)
+        }
+    }
+
+    fn remove_subscriber(&self, subscriber: &Rc<dyn TreeSubscriber>) {
+        let subscriber = Rc::downgrade(subscriber);
+        self.subscribers
+            .borrow_mut()
+            .retain(|c| !c.ptr_eq(&subscriber))
+    }
+
+    fn sticky_header(&self, index: usize) -> Option<usize> {
+        let flat = self.flat.borrow();
+        let mut node = flat.get_index(index).map(|v| v.1.clone());
+
+        while let Some(current) = node {
+            if current.is_root() {
+                break;
+            }
+
+            if current.is_group_header() {
+                return flat.get_index_of(&current.key());
+            }
+
+            node = current.parent();
+        }
+
+        None
+    }
+
+    fn clear_filter(&self) -> bool {
+        if !self.has_filter() {
+            return false;
+        }
+
+        self.set_filter(None);
+        true
+    }
+
+    fn collapse(&self, key: KeyType) -> bool {
+        let Some(item) = self.flat.borrow().get(&key).cloned() else {
+            return false;
+        };
+
+        // a loading node collapses by cancelling its load
+        if !item
+            .flags()
+            .intersects(TreeFlags::EXPANDED | TreeFlags::LOADING)
+        {
+            return false;
+        }
+
+        self.expand(key);
+        true
+    }
+
+    fn expand(&self, key: KeyType) -> bool {
+        let Some(item) = self.flat.borrow().get(&key).cloned() else {
+            return false;
+        };
+
+        let flags = item.flags();
+        if !flags.contains(TreeFlags::EXPANDABLE)
+            || flags.intersects(TreeFlags::EXPANDED | TreeFlags::LOADING)
+        {
+            return false;
+        }
+
+        DynamicTree::expand(self, key);
+        true
+    }
+
+    fn expand_all(&self) {
+        DynamicTree::expand_all(self)
+    }
+
+    fn collapse_all(&self) {
+        DynamicTree::collapse_all(self)
+    }
+
+    fn all_expanded(&self) -> bool {
+        let mut all_expanded = true;
+        self.root.walk(&mut |node| {
+            let flags = node.flags();
+            if flags.contains(TreeFlags::EXPANDABLE | TreeFlags::READY)
+                && !flags.contains(TreeFlags::EXPANDED)
+            {
+                all_expanded = false;
+            }
+        });
+
+        all_expanded
+    }
+
+    fn parent(&self, key: KeyType) -> Option<KeyType> {
+        let item = self.flat.borrow().get(&key).cloned()?;
+        let parent = item.parent()?;
+
+        (!parent.is_root()).then(|| parent.key())
+    }
+
+    fn is_frozen(&self) -> bool {
+        DynamicTree::is_frozen(self)
+    }
+
+    fn index_of(&self, key: KeyType) -> Option<usize> {
+        DynamicTree::index_of(self, key)
+    }
+
+    fn selected_keys(&self) -> Vec<KeyType> {
+        DynamicTree::selected_keys(self)
+    }
+
+    fn is_loading(&self) -> bool {
+        self.root.flags().contains(TreeFlags::LOADING)
+    }
+
+    fn is_selected(&self, key: KeyType) -> bool {
+        DynamicTree::is_selected(self, key)
+    }
+
+    fn toggle_checked(&self, key: KeyType) {
+        DynamicTree::toggle_checked(self, key)
+    }
+
+    fn checked_keys(&self) -> Vec<KeyType> {
+        DynamicTree::checked_keys(self)
+    }
+
+    fn reveal(&self, key: KeyType) -> bool {
+        DynamicTree::reveal(self, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::channel::oneshot;
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+    use wasm_bindgen::JsValue;
+
+    use super::*;
+    use crate::node::tests::{expanded_root, node, node_with_flags};
+    use crate::plain::PlainTreeData;
+    use crate::provider::{AsyncTreeProvider, TreeExpandResult};
+    use crate::ROOT_KEY;
+
+    #[derive(Default)]
+    struct Recorder {
+        update_all: Cell<usize>,
+        update_item: Cell<usize>,
+        update_items: Cell<usize>,
+        changes: RefCell<Vec<TreeChange>>,
+    }
+
+    impl TreeSubscriber for Recorder {
+        fn update_all(&self) {
+            self.update_all.set(self.update_all.get() + 1);
+        }
+
+        fn update_item(&self, _key: usize) {
+            self.update_item.set(self.update_item.get() + 1);
+        }
+
+        fn update_items(&self, keys: &[KeyType]) {
+            self.update_items.set(self.update_items.get() + 1);
+            self.update_item.set(self.update_item.get() + keys.len());
+        }
+
+        fn update_rows(&self, change: &TreeChange) {
+            self.changes.borrow_mut().push(change.clone());
+            self.update_all();
+        }
+    }
+
+    fn sample() -> Rc<TreeNode> {
+        expanded_root().build(|root| vec![node(root, 1, "1"), node(root, 2, "2")])
+    }
+
+    #[test]
+    fn clear() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        assert_eq!(tree.count(), 2);
+
+        tree.clear();
+
+        assert_eq!(tree.count(), 0);
+        assert_eq!(tree.root().children_len(), 0);
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    #[test]
+    fn subscribe_once() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+        tree.add_subscriber(recorder.clone());
+
+        tree.clear();
+        assert_eq!(recorder.update_all.get(), 1);
+
+        let subscriber: Rc<dyn TreeSubscriber> = recorder.clone();
+        tree.remove_subscriber(&subscriber);
+        tree.clear();
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    #[test]
+    fn visible_follows_expand() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE | TreeFlags::READY)
+                    .build(|parent| vec![node(parent, 11, "1.1")]),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let keys = || tree.visible().keys().copied().collect::<Vec<_>>();
+        assert_eq!(keys(), [1, 2]);
+
+        tree.expand(1);
+        assert_eq!(keys(), [1, 11, 2]);
+        assert_eq!(&*tree.visible().get_index(1).unwrap().1.title(), "1.1");
+
+        tree.expand(1);
+        assert_eq!(keys(), [1, 2]);
+    }
+
+    #[test]
+    fn expand_emits_changes() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node(root, 1, "1"),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE | TreeFlags::READY)
+                    .build(|parent| vec![node(parent, 21, "2.1"), node(parent, 22, "2.2")]),
+                node(root, 3, "3"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.expand(2);
+        tree.expand(2);
+
+        assert_eq!(
+            recorder.changes.take(),
+            [
+                TreeChange {
+                    removed: 2..2,
+                    inserted: 2..4,
+                },
+                TreeChange {
+                    removed: 2..4,
+                    inserted: 2..2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn two_root_forest() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let scratch = TreeNode::root();
+        let workspace = node_with_flags(&scratch, 1, "Workspace", folder).build(|parent| {
+            vec![
+                node_with_flags(parent, 11, "src", folder)
+                    .build(|parent| vec![node(parent, 111, "lib.rs")]),
+                node(parent, 12, "Cargo.toml"),
+            ]
+        });
+        let editors = node_with_flags(&scratch, 2, "Open Editors", folder)
+            .build(|parent| vec![node(parent, 21, "main.rs")]);
+
+        let tree = DynamicTree::forest(vec![workspace, editors], Default::default());
+        assert_eq!(scratch.children_len(), 0);
+
+        let rows = |tree: &DynamicTree| {
+            (0..tree.count())
+                .map(|index| {
+                    let item = tree.item(index);
+                    (item.key(), item.depth())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(rows(&tree), [(1, 1), (2, 1)]);
+
+        tree.expand(2);
+        assert_eq!(rows(&tree), [(1, 1), (2, 1), (21, 2)]);
+
+        tree.expand(1);
+        tree.expand(11);
+        assert_eq!(
+            rows(&tree),
+            [(1, 1), (11, 2), (111, 3), (12, 2), (2, 1), (21, 2)]
+        );
+
+        tree.expand(1);
+        assert_eq!(rows(&tree), [(1, 1), (2, 1), (21, 2)]);
+    }
+
+    #[test]
+    fn count_before_flatten() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE | TreeFlags::READY)
+                    .build(|parent| vec![node(parent, 11, "1.1")]),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert_eq!(tree.count(), 2);
+
+        let folder = tree.find(1).unwrap();
+        folder.set_flags(folder.flags() | TreeFlags::EXPANDED);
+
+        let keys = |tree: &DynamicTree| {
+            (0..tree.count())
+                .map(|index| tree.item(index).key())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(&tree), [1, 11, 2]);
+
+        tree.flatten();
+        assert_eq!(keys(&tree), [1, 11, 2]);
+
+        folder.set_flags(folder.flags() - TreeFlags::EXPANDED);
+        assert_eq!(keys(&tree), [1, 2]);
+    }
+
+    #[test]
+    fn drop_subscription() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        let subscription = tree.subscribe(recorder.clone());
+
+        tree.clear();
+        assert_eq!(recorder.update_all.get(), 1);
+
+        drop(subscription);
+        tree.clear();
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    struct Panicking;
+
+    impl TreeSubscriber for Panicking {
+        fn update_all(&self) {
+            panic!("subscriber failed")
+        }
+
+        fn update_item(&self, _key: usize) {}
+    }
+
+    #[test]
+    fn panicking_subscriber() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let first = Rc::new(Recorder::default());
+        let last = Rc::new(Recorder::default());
+        let panicking = Rc::new(Panicking);
+        tree.add_subscriber(first.clone());
+        tree.add_subscriber(panicking.clone());
+        tree.add_subscriber(last.clone());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| tree.clear()));
+        assert!(result.is_err());
+        assert_eq!(first.update_all.get(), 1);
+        assert_eq!(last.update_all.get(), 1);
+    }
+
+    #[test]
+    fn insert_children() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        let parent = tree.root().get(1).unwrap();
+        tree.insert_children(1, vec![node(&parent, 11, "1.1"), node(&parent, 12, "1.2")]);
+
+        assert_eq!(tree.count(), 4);
+        assert_eq!(recorder.update_all.get(), 1);
+
+        // collapsed parent keeps the visible list as is
+        let parent = tree.root().get(2).unwrap();
+        tree.insert_children(2, vec![node(&parent, 21, "2.1")]);
+        assert_eq!(tree.count(), 4);
+        assert_eq!(tree.root().children_len(), 5);
+
+        tree.insert_children(42, vec![]);
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    #[test]
+    fn selection_change_deltas() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        tree.set_sync_updates(true);
+
+        let mut changes = vec![];
+        for key in [1, 2, 1, 2] {
+            let before = tree.selection.borrow().clone();
+            tree.toggle_selected(key);
+            changes.push(SelectionChange::diff(&before, &tree.selection.borrow()).unwrap());
+        }
+
+        let deltas = changes
+            .iter()
+            .map(|c| (c.added.clone(), c.removed.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            deltas,
+            [
+                (vec![1], vec![]),
+                (vec![2], vec![]),
+                (vec![], vec![1]),
+                (vec![], vec![2]),
+            ]
+        );
+
+        assert_eq!(changes[1].current, [1, 2]);
+        assert!(tree.selected_keys().is_empty());
+
+        tree.select(2);
+        let selection = tree.selection.borrow().clone();
+        tree.select(2);
+        assert_eq!(
+            SelectionChange::diff(&selection, &tree.selection.borrow()),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_display() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "Loading…", TreeFlags::UNRESOLVED)]);
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        let node = tree.root().get(1).unwrap();
+        assert_eq!(&*node.title(), "Loading…");
+
+        tree.apply_display(
+            &node,
+            Box::new(PlainTreeData {
+                key: 1,
+                icon: Some("file".into()),
+                title: "main.rs".into(),
+                flags: TreeFlags::empty(),
+            }),
+        );
+
+        assert_eq!(&*node.title(), "main.rs");
+        assert_eq!(node.icon().as_deref(), Some("file"));
+        assert_eq!(recorder.update_item.get(), 1);
+    }
+
+    #[test]
+    fn total_visible_height() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder)
+                    .build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")]),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let height_of = |item: &dyn TreeItem| if item.expandable() { 32 } else { 24 };
+
+        assert_eq!(tree.total_visible_height(&height_of), 32 + 24 * 3);
+
+        tree.expand(1);
+        assert_eq!(tree.total_visible_height(&height_of), 32 + 24);
+    }
+
+    #[test]
+    fn frozen() {
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(
+                root,
+                1,
+                "1",
+                TreeFlags::EXPANDABLE | TreeFlags::READY,
+            )]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.set_frozen(true);
+        assert_eq!(recorder.update_all.get(), 1);
+
+        let parent = tree.root().get(1).unwrap();
+        tree.insert_children(1, vec![node(&parent, 11, "1.1")]);
+        tree.handle_click(1, Default::default());
+        tree.expand_all();
+
+        assert_eq!(tree.count(), 1);
+        assert_eq!(tree.root().children_len(), 1);
+        assert!(!parent.flags().contains(TreeFlags::EXPANDED));
+        assert_eq!(recorder.update_all.get(), 1);
+
+        tree.set_frozen(false);
+        tree.insert_children(1, vec![node(&parent, 11, "1.1")]);
+        tree.expand(1);
+
+        assert_eq!(tree.count(), 2);
+        assert!(parent.flags().contains(TreeFlags::EXPANDED));
+    }
+
+    #[test]
+    fn coalesce_item_updates() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        assert!(tree.queue_update_item(1));
+        for key in [2, 1, 2] {
+            assert!(!tree.queue_update_item(key));
+        }
+
+        tree.flush_updates();
+        tree.flush_updates();
+
+        assert_eq!(recorder.update_items.get(), 1);
+        assert_eq!(recorder.update_item.get(), 2);
+    }
+
+    #[test]
+    fn expand_all_keeps_lazy_subtrees() {
+        let loaded = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", loaded).build(|parent| {
+                    vec![node_with_flags(parent, 11, "1.1", loaded)
+                        .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                }),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.expand_all();
+        assert_eq!(tree.count(), 4);
+        assert_eq!(recorder.update_all.get(), 1);
+
+        let lazy = tree.root().get(2).unwrap().flags();
+        assert!(!lazy.intersects(TreeFlags::EXPANDED | TreeFlags::LOADING));
+
+        tree.collapse_all();
+        assert_eq!(tree.count(), 2);
+        assert_eq!(tree.root().children_len(), 4);
+        assert_eq!(recorder.update_all.get(), 2);
+    }
+
+    #[test]
+    fn async_root() {
+        let root = TreeNode::root();
+        root.set_flags(root.flags() | TreeFlags::LOADING);
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        assert!(tree.is_loading());
+        assert_eq!(tree.count(), 0);
+
+        let root = tree.root();
+        tree.insert_children(ROOT_KEY, vec![node(&root, 1, "1"), node(&root, 2, "2")]);
+
+        assert!(!tree.is_loading());
+        assert_eq!(tree.count(), 2);
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    #[test]
+    fn index_of_round_trip() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE | TreeFlags::READY)
+                    .build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")]),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE | TreeFlags::READY)
+                    .build(|parent| vec![node(parent, 21, "2.1")]),
+                node(root, 3, "3"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.expand(1);
+
+        for index in 0..tree.count() {
+            assert_eq!(tree.index_of(tree.item(index).key()), Some(index));
+        }
+
+        // collapsed and unknown keys have no row
+        assert_eq!(tree.index_of(21), None);
+        assert_eq!(tree.index_of(42), None);
+    }
+
+    #[test]
+    fn path_string() {
+        let root = expanded_root().build(|root| {
+            vec![node(root, 1, "src").build(|parent| {
+                vec![node(parent, 11, "view").build(|parent| vec![node(parent, 111, "mod.rs")])]
+            })]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert_eq!(
+            tree.path_string(111, "/").as_deref(),
+            Some("src/view/mod.rs")
+        );
+        assert_eq!(
+            tree.path_string(111, " > ").as_deref(),
+            Some("src > view > mod.rs")
+        );
+        assert_eq!(tree.path_string(1, "/").as_deref(), Some("src"));
+        assert_eq!(tree.path_string(ROOT_KEY, "/"), None);
+        assert_eq!(tree.path_string(42, "/"), None);
+    }
+
+    #[test]
+    fn reveal() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE).build(|parent| {
+                    vec![node_with_flags(parent, 11, "1.1", TreeFlags::EXPANDABLE)
+                        .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert_eq!(tree.index_of(111), None);
+
+        tree.set_frozen(true);
+        assert!(!tree.reveal(111));
+        assert_eq!(tree.count(), 2);
+
+        tree.set_frozen(false);
+        assert!(tree.reveal(111));
+        assert_eq!(tree.index_of(111), Some(2));
+        assert_eq!(tree.count(), 4);
+
+        assert!(!tree.reveal(42));
+    }
+
+    #[test]
+    fn set_data_if_changed() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        fn data(title: &str, icon: Option<&'static str>) -> Box<dyn TreeData> {
+            Box::new(PlainTreeData {
+                key: 1,
+                icon: icon.map(Into::into),
+                title: title.into(),
+                flags: TreeFlags::empty(),
+            })
+        }
+
+        assert!(!tree.set_data_if_changed(1, data("1", None)));
+        assert_eq!(recorder.update_item.get(), 0);
+
+        assert!(tree.set_data_if_changed(1, data("1", Some("file"))));
+        assert!(tree.set_data_if_changed(1, data("one", Some("file"))));
+        assert_eq!(recorder.update_item.get(), 2);
+
+        assert!(!tree.set_data_if_changed(42, data("1", None)));
+    }
+
+    #[test]
+    fn click_selects() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.handle_click(1, Default::default());
+        assert_eq!(tree.selected_keys(), [1]);
+        assert_eq!(recorder.update_item.get(), 1);
+
+        // both the old and the new row are repainted
+        tree.handle_click(2, Default::default());
+        assert_eq!(tree.selected_keys(), [2]);
+        assert!(!tree.is_selected(1));
+        assert_eq!(recorder.update_item.get(), 3);
+    }
+
+    #[test]
+    fn click_removed_row() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        tree.set_sync_updates(true);
+        tree.select(1);
+
+        // the row is still painted until the next frame
+        tree.remove(2);
+        tree.handle_click(2, Default::default());
+        tree.handle_dbl_click(2);
+        tree.expand(2);
+        assert_eq!(tree.selected_keys(), [1]);
+    }
+
+    #[test]
+    fn multi_selection() {
+        let root = expanded_root().build(|root| (1..=5).map(|key| node(root, key, "")).collect());
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+
+        let toggle = ClickModifiers {
+            toggle: true,
+            range: false,
+        };
+        let range = ClickModifiers {
+            toggle: false,
+            range: true,
+        };
+
+        tree.handle_click(4, Default::default());
+        tree.handle_click(2, range);
+        assert_eq!(tree.selected_keys(), [2, 3, 4]);
+
+        // the anchor stays at the first clicked row
+        tree.handle_click(5, range);
+        assert_eq!(tree.selected_keys(), [4, 5]);
+
+        tree.handle_click(1, toggle);
+        assert_eq!(tree.selected_keys(), [1, 4, 5]);
+
+        tree.handle_click(4, toggle);
+        assert_eq!(tree.selected_keys(), [1, 5]);
+
+        // ranges start from the last toggled row
+        tree.handle_click(2, range);
+        assert_eq!(tree.selected_keys(), [2, 3, 4]);
+    }
+
+    #[test]
+    fn failed_expand() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        let item = tree.root().get(1).unwrap();
+        item.set_flags(item.flags() | TreeFlags::LOADING);
+
+        tree.finish_expand(item.clone(), Err(ProviderError::Js(JsValue::NULL)));
+
+        let flags = item.flags();
+        assert!(!flags.intersects(TreeFlags::LOADING | TreeFlags::EXPANDED | TreeFlags::READY));
+        assert_eq!(tree.count(), 1);
+        assert_eq!(recorder.update_all.get(), 1);
+
+        // a retry goes through the provider again
+        item.set_flags(flags | TreeFlags::LOADING);
+        tree.finish_expand(item.clone(), Ok(vec![node(&item, 11, "1.1")]));
+
+        assert!(item
+            .flags()
+            .contains(TreeFlags::EXPANDED | TreeFlags::READY));
+        assert_eq!(tree.count(), 2);
+        assert_eq!(recorder.update_all.get(), 2);
+    }
+
+    type ChildrenSender = oneshot::Sender<Result<Vec<Rc<TreeNode>>, JsValue>>;
+
+    struct AsyncProvider {
+        root: Rc<TreeNode>,
+        sender: RefCell<Option<ChildrenSender>>,
+    }
+
+    impl TreeProvider for AsyncProvider {
+        fn root(&self) -> Rc<TreeNode> {
+            self.root.clone()
+        }
+
+        fn expand(&self, _node: &Rc<TreeNode>) -> TreeExpandResult {
+            let (sender, receiver) = oneshot::channel();
+            self.sender.replace(Some(sender));
+            TreeExpandResult::Async(receiver)
+        }
+    }
+
+    #[test]
+    fn expand_result_paths() {
+        // static trees are their own provider and expand synchronously
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+        let tree = DynamicTree::new(root, Default::default());
+
+        let parent = tree.root().get(1).unwrap();
+        parent.insert(vec![node(&parent, 11, "1.1")]);
+        tree.expand(1);
+        assert_eq!(tree.count(), 2);
+
+        // channels and futures are awaited by the tree
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+        let provider = Rc::new(AsyncProvider {
+            root,
+            sender: Default::default(),
+        });
+
+        let (tree, mut pool) = spawned(DynamicTree::new(provider.clone(), Default::default()));
+        tree.set_sync_updates(true);
+        tree.expand(1);
+        let item = tree.root().get(1).unwrap();
+        assert!(item.flags().contains(TreeFlags::LOADING));
+
+        let sender = provider.sender.take().unwrap();
+        sender.send(Ok(vec![node(&item, 11, "1.1")])).unwrap();
+        pool.run_until_stalled();
+        assert_eq!(tree.count(), 2);
+        assert!(!item.flags().contains(TreeFlags::LOADING));
+
+        let provider = Rc::new(FetchProvider {
+            root: expanded_root()
+                .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]),
+        });
+
+        let (tree, mut pool) = spawned(DynamicTree::new(provider, Default::default()));
+        tree.set_sync_updates(true);
+        tree.expand(1);
+        pool.run_until_stalled();
+        assert_eq!(tree.count(), 2);
+    }
+
+    // Loads run on the returned pool instead of `spawn_local`
+    fn spawned(tree: Rc<DynamicTree>) -> (Rc<DynamicTree>, LocalPool) {
+        let pool = LocalPool::new();
+        let spawner = pool.spawner();
+        tree.set_spawner(Rc::new(move |future| spawner.spawn_local(future).unwrap()));
+        (tree, pool)
+    }
+
+    #[test]
+    fn leaf_ignores_expand() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.expand(1);
+
+        let leaf = tree.root().get(1).unwrap();
+        assert!(!leaf.expandable());
+        assert!(!leaf
+            .flags()
+            .intersects(TreeFlags::EXPANDED | TreeFlags::LOADING));
+        assert_eq!(recorder.update_all.get(), 0);
+        assert_eq!(recorder.update_item.get(), 0);
+    }
+
+    #[test]
+    fn loading_rows() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+
+        let tree = DynamicTree::new(root, Default::default());
+        let item = tree.root().get(1).unwrap();
+
+        item.set_flags(item.flags() | TreeFlags::LOADING);
+        tree.flatten();
+
+        let rows = (0..tree.count())
+            .map(|index| tree.item(index))
+            .map(|item| (item.title().to_string(), item.loading()))
+            .collect::<Vec<_>>();
+        assert_eq!(rows, [("1".into(), true), ("Loading…".into(), true)]);
+
+        tree.finish_expand(item, Ok(vec![]));
+        assert_eq!(tree.count(), 1);
+        assert!(!tree.item(0).loading());
+    }
+
+    #[test]
+    fn placeholder_rows_are_inert() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let item = tree.root().get(1).unwrap();
+        item.set_flags(item.flags() | TreeFlags::LOADING);
+        tree.flatten();
+
+        let placeholder = PlaceholderData::key_for(1);
+        assert!(PlaceholderData::is_key(placeholder));
+        assert!(!PlaceholderData::is_key(1));
+        assert!(!PlaceholderData::is_key(ROOT_KEY));
+        assert!(PlaceholderData::is_reserved(PlaceholderData::key_for(0)));
+        assert_eq!(tree.index_of(placeholder), Some(1));
+        assert!(tree.find(placeholder).is_none());
+
+        tree.handle_click(placeholder, Default::default());
+        tree.toggle_selected(placeholder);
+        assert!(tree.selected_keys().is_empty());
+
+        // ranges skip the row
+        tree.select(1);
+        tree.select_range(2);
+        assert_eq!(tree.selected_keys(), [1, 2]);
+    }
+
+    #[test]
+    fn remove() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder)
+                    .build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")]),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.toggle_selected(12);
+        tree.toggle_selected(2);
+
+        assert_eq!(tree.remove(1).unwrap().key(), 1);
+        assert_eq!(tree.count(), 1);
+        assert_eq!(tree.root().children_len(), 1);
+        assert_eq!(tree.selected_keys(), [2]);
+        assert_eq!(recorder.update_all.get(), 1);
+
+        assert!(tree.remove(1).is_none());
+        assert!(tree.remove(ROOT_KEY).is_none());
+    }
+
+    #[test]
+    fn drop_positions() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let root = expanded_root().build(|root| {
+            vec![
+                node(root, 1, "1"),
+                node(root, 2, "2"),
+                node(root, 3, "3"),
+                node_with_flags(root, 4, "4", folder | TreeFlags::EXPANDED)
+                    .build(|parent| vec![node(parent, 41, "4.1")]),
+                node_with_flags(root, 5, "5", TreeFlags::EXPANDABLE),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let at = |key, position| DropTarget { key, position };
+        let keys = |tree: &DynamicTree| tree.flat.borrow().keys().copied().collect::<Vec<_>>();
+
+        // between items on the same level, in both directions
+        tree.handle_drop(1, at(3, DropPosition::After));
+        assert_eq!(keys(&tree), [2, 3, 1, 4, 41, 5]);
+        tree.handle_drop(1, at(2, DropPosition::Before));
+        assert_eq!(keys(&tree), [1, 2, 3, 4, 41, 5]);
+
+        // after an expanded folder means before its first child
+        tree.handle_drop(2, at(4, DropPosition::After));
+        assert_eq!(keys(&tree), [1, 3, 4, 2, 41, 5]);
+        assert_eq!(tree.find(2).unwrap().depth(), 2);
+
+        tree.handle_drop(3, at(41, DropPosition::Inside));
+        tree.handle_drop(3, at(5, DropPosition::Inside));
+        assert_eq!(keys(&tree), [1, 3, 4, 2, 41, 5]);
+
+        assert!(tree.can_drop(1, at(4, DropPosition::Inside)));
+        assert!(!tree.can_drop(4, at(41, DropPosition::Before)));
+        assert!(!tree.can_drop(4, at(4, DropPosition::After)));
+        assert_eq!(
+            tree.drop_node(4, at(2, DropPosition::Before)),
+            Err(MoveError::IntoDescendant)
+        );
+        assert_eq!(
+            tree.drop_node(1, at(3, DropPosition::Inside)),
+            Err(MoveError::NotAFolder)
+        );
+        assert_eq!(
+            tree.drop_node(1, at(5, DropPosition::Inside)),
+            Err(MoveError::NotLoaded)
+        );
+    }
+
+    #[test]
+    fn move_node() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![node_with_flags(parent, 11, "1.1", folder)
+                        .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                }),
+                node_with_flags(root, 2, "2", folder).build(|parent| vec![node(parent, 21, "2.1")]),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        assert_eq!(tree.move_node(1, 111, 0), Err(MoveError::IntoDescendant));
+        assert_eq!(tree.move_node(1, 1, 0), Err(MoveError::IntoDescendant));
+        assert_eq!(tree.move_node(42, 1, 0), Err(MoveError::UnknownNode));
+        assert_eq!(tree.move_node(11, 42, 0), Err(MoveError::UnknownParent));
+
+        tree.move_node(11, 2, 0).unwrap();
+
+        let keys = tree.flat.borrow().keys().copied().collect::<Vec<_>>();
+        assert_eq!(keys, [1, 2, 11, 111, 21]);
+
+        let root = tree.root();
+        assert_eq!(root.get(1).unwrap().children_len(), 0);
+        assert_eq!(root.get(2).unwrap().children_len(), 3);
+        assert_eq!(root.children_len(), 5);
+
+        let moved = root.find(11).unwrap();
+        assert_eq!(moved.parent().unwrap().key(), 2);
+        assert_eq!(recorder.update_all.get(), 1);
+    }
+
+    #[test]
+    fn find_collapsed() {
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)
+                .build(|parent| vec![node(parent, 11, "1.1")])]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert_eq!(tree.count(), 1);
+
+        assert_eq!(tree.find(11).unwrap().path_keys(), [1, 11]);
+        assert!(tree.find(42).is_none());
+    }
+
+    fn title_contains(needle: &'static str) -> TreeFilter {
+        Rc::new(move |data: &dyn TreeData| data.title().contains(needle))
+    }
+
+    fn filter_sample() -> Rc<TreeNode> {
+        expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "src", TreeFlags::EXPANDABLE).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "view", TreeFlags::EXPANDABLE)
+                            .build(|parent| vec![node(parent, 111, "needle.rs")]),
+                        node(parent, 12, "lib.rs"),
+                    ]
+                }),
+                node(root, 2, "Cargo.toml"),
+            ]
+        })
+    }
+
+    #[test]
+    fn filter_deep_leaf() {
+        let tree = DynamicTree::new(filter_sample(), Default::default());
+        assert_eq!(tree.count(), 2);
+
+        tree.set_filter(Some(title_contains("needle")));
+        assert!(tree.has_filter());
+        assert_eq!(
+            tree.flat.borrow().keys().copied().collect::<Vec<_>>(),
+            [1, 11, 111]
+        );
+
+        // the filter is applied again when the list changes
+        tree.insert_children(11, vec![node(&tree.find(11).unwrap(), 112, "needle2.rs")]);
+        assert_eq!(tree.count(), 4);
+
+        tree.set_filter(Some(title_contains("nothing")));
+        assert_eq!(tree.count(), 0);
+    }
+
+    #[test]
+    fn clear_filter() {
+        let tree = DynamicTree::new(filter_sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        // manually expanded before filtering, stays expanded afterwards
+        tree.expand(1);
+        assert_eq!(tree.count(), 4);
+
+        tree.set_filter(Some(title_contains("needle")));
+        assert_eq!(tree.count(), 3);
+
+        assert!(TreeController::clear_filter(&*tree));
+        assert!(!TreeController::clear_filter(&*tree));
+        assert_eq!(recorder.update_all.get(), 3);
+
+        assert_eq!(
+            tree.flat.borrow().keys().copied().collect::<Vec<_>>(),
+            [1, 11, 12, 2]
+        );
+        assert!(!tree.find(11).unwrap().expanded());
+    }
+
+    #[test]
+    fn title_ranges() {
+        let root = expanded_root().build(|root| vec![node(root, 1, "main.rs")]);
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        tree.set_title_ranges(1, Some(vec![0..2, 5..7]));
+        tree.set_title_ranges(1, Some(vec![0..2, 5..7]));
+        assert_eq!(tree.find(1).unwrap().title_ranges(), Some(vec![0..2, 5..7]));
+        assert_eq!(recorder.update_item.get(), 1);
+
+        tree.set_title_ranges(1, None);
+        assert_eq!(recorder.update_item.get(), 2);
+    }
+
+    #[test]
+    fn expand_on_double_click() {
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)
+                .build(|parent| vec![node(parent, 11, "1.1")])]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+
+        // by default only single clicks expand
+        tree.handle_dbl_click(1);
+        assert_eq!(tree.count(), 1);
+
+        tree.set_expand_on(ExpandOn::DoubleClick);
+        tree.handle_click(1, Default::default());
+        assert!(tree.is_selected(1));
+        assert_eq!(tree.count(), 1);
+
+        tree.handle_dbl_click(1);
+        assert_eq!(tree.count(), 2);
+    }
+
+    #[test]
+    fn context_menu_keeps_state() {
+        let tree = DynamicTree::new(sample(), Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        // only forwards to the callback, unknown keys are ignored
+        tree.handle_context_menu(1, 10, 20);
+        tree.handle_context_menu(42, 10, 20);
+
+        assert!(tree.selected_keys().is_empty());
+        assert_eq!(recorder.update_all.get(), 0);
+    }
+
+    #[test]
+    fn tri_state_checkboxes() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", TreeFlags::EXPANDABLE).build(|parent| {
+                            vec![node(parent, 111, "1.1.1"), node(parent, 112, "1.1.2")]
+                        }),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let state = |key| tree.find(key).unwrap().check_state();
+
+        tree.set_checked(111, true);
+        assert_eq!(state(11), CheckState::Indeterminate);
+        assert_eq!(state(1), CheckState::Indeterminate);
+        assert_eq!(state(2), CheckState::Unchecked);
+
+        tree.toggle_checked(112);
+        tree.toggle_checked(12);
+        assert_eq!(state(11), CheckState::Checked);
+        assert_eq!(state(1), CheckState::Checked);
+        assert_eq!(tree.checked_keys(), [1, 11, 111, 112, 12]);
+
+        // unchecking a folder clears its subtree
+        tree.toggle_checked(11);
+        assert_eq!(state(112), CheckState::Unchecked);
+        assert_eq!(state(1), CheckState::Indeterminate);
+
+        // indeterminate nodes become checked with the whole subtree
+        tree.toggle_checked(1);
+        assert_eq!(tree.checked_keys(), [1, 11, 111, 112, 12]);
+
+        tree.toggle_checked(1);
+        assert!(tree.checked_keys().is_empty());
+    }
+
+    #[test]
+    fn loaded_children_inherit_checked() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        tree.set_checked(1, true);
+
+        for key in [1, 2] {
+            let item = tree.find(key).unwrap();
+            let children = vec![node(&item, key * 10 + 1, "child")
+                .build(|parent| vec![node(parent, key * 100 + 11, "grandchild")])];
+
+            tree.finish_expand(item, Ok(children));
+        }
+
+        assert_eq!(tree.checked_keys(), [1, 11, 111]);
+    }
+
+    #[test]
+    fn restore_snapshot() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let build = || {
+            expanded_root().build(|root| {
+                vec![
+                    node_with_flags(root, 1, "1", folder).build(|parent| {
+                        vec![node_with_flags(parent, 11, "1.1", folder)
+                            .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                    }),
+                    node_with_flags(root, 2, "2", folder)
+                        .build(|parent| vec![node(parent, 21, "2.1")]),
+                    // lazy, loads when restored
+                    node_with_flags(root, 3, "3", TreeFlags::EXPANDABLE),
+                ]
+            })
+        };
+
+        let tree = DynamicTree::new(build(), Default::default());
+        tree.set_sync_updates(true);
+        tree.expand(1);
+        tree.expand(11);
+        tree.select(111);
+        let snapshot = tree.snapshot();
+        assert_eq!(
+            snapshot,
+            TreeSnapshot {
+                expanded: vec![1, 11],
+                selected: vec![111],
+            }
+        );
+
+        let tree = DynamicTree::new(build(), Default::default());
+        tree.set_sync_updates(true);
+        tree.restore(&TreeSnapshot {
+            expanded: vec![3, 11, 1, 42],
+            selected: vec![111, 42],
+        });
+
+        assert_eq!(tree.expansion_snapshot(), [1, 11, 3]);
+        assert_eq!(tree.selected_keys(), [111]);
+        assert_eq!(tree.count(), 5);
+    }
+
+    #[test]
+    fn restore_into_lazy_subtree() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+        let provider = Rc::new(AsyncProvider {
+            root,
+            sender: Default::default(),
+        });
+
+        let (tree, mut pool) = spawned(DynamicTree::new(provider.clone(), Default::default()));
+        tree.set_sync_updates(true);
+
+        // nothing is loading, so missing keys are dropped right away
+        tree.restore_expansion(&[11]);
+        assert!(tree.pending_expansion.borrow().is_empty());
+
+        // not loaded yet, expands once its parent is
+        tree.restore_expansion(&[1, 11, 42]);
+        // the folder and its placeholder row
+        assert_eq!(tree.count(), 2);
+
+        let item = tree.find(1).unwrap();
+        let folder = node_with_flags(&item, 11, "1.1", TreeFlags::EXPANDABLE | TreeFlags::READY)
+            .build(|parent| vec![node(parent, 111, "1.1.1")]);
+        let sender = provider.sender.take().unwrap();
+        sender.send(Ok(vec![folder])).unwrap();
+        pool.run_until_stalled();
+
+        assert_eq!(tree.expansion_snapshot(), [1, 11]);
+        assert_eq!(tree.count(), 3);
+        // never showed up
+        assert!(tree.pending_expansion.borrow().is_empty());
+    }
+
+    #[test]
+    fn pending_expansion_is_dropped() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE | TreeFlags::READY),
+            ]
+        });
+        let provider = Rc::new(AsyncProvider {
+            root,
+            sender: Default::default(),
+        });
+
+        let (tree, _pool) = spawned(DynamicTree::new(provider, Default::default()));
+        let pending = || {
+            tree.pending_expansion
+                .borrow()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        tree.expand(1);
+        tree.restore_expansion(&[11, 21]);
+        assert_eq!(pending(), [11, 21]);
+
+        // inserted by hand, nothing expands it
+        tree.insert_children(2, vec![node(&tree.find(2).unwrap(), 21, "2.1")]);
+        tree.remove(2);
+        assert_eq!(pending(), [11]);
+
+        tree.clear();
+        assert!(pending().is_empty());
+    }
+
+    #[test]
+    fn restore_selection_into_lazy_subtree() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+        let provider = Rc::new(AsyncProvider {
+            root,
+            sender: Default::default(),
+        });
+
+        let (tree, mut pool) = spawned(DynamicTree::new(provider.clone(), Default::default()));
+        tree.set_sync_updates(true);
+
+        tree.restore(&TreeSnapshot {
+            expanded: vec![1],
+            selected: vec![11, 42],
+        });
+        // kept until the folder loads
+        assert!(tree.selected_keys().is_empty());
+        assert_eq!(tree.pending_selection.borrow().len(), 2);
+
+        let item = tree.find(1).unwrap();
+        let sender = provider.sender.take().unwrap();
+        sender.send(Ok(vec![node(&item, 11, "1.1")])).unwrap();
+        pool.run_until_stalled();
+
+        assert_eq!(tree.expansion_snapshot(), [1]);
+        assert_eq!(tree.selected_keys(), [11]);
+        // never showed up
+        assert!(tree.pending_selection.borrow().is_empty());
+    }
+
+    struct FetchProvider {
+        root: Rc<TreeNode>,
+    }
+
+    impl AsyncTreeProvider for FetchProvider {
+        fn root(&self) -> Rc<TreeNode> {
+            self.root.clone()
+        }
+
+        fn expand(
+            &self,
+            parent: &Rc<TreeNode>,
+        ) -> LocalBoxFuture<'static, Result<Vec<Rc<TreeNode>>, ProviderError>> {
+            let parent = parent.clone();
+            async move {
+                match parent.key() {
+                    1 => Ok(vec![node(&parent, 11, "1.1")]),
+                    _ => Err(ProviderError::Message("not found".into())),
+                }
+            }
+            .boxed_local()
+        }
+    }
+
+    #[test]
+    fn sync_and_async_providers() {
+        let build = || {
+            expanded_root().build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)])
+        };
+
+        // static trees load in place
+        let tree = DynamicTree::new(build(), Default::default());
+        let item = tree.find(1).unwrap();
+        assert!(tree.provider.expand(&item).into_job().is_none());
+
+        // async providers hand the tree a future to await
+        let provider = Rc::new(FetchProvider { root: build() });
+        let tree = DynamicTree::new(provider, Default::default());
+        let item = tree.find(1).unwrap();
+        let job = tree.provider.expand(&item).into_job().unwrap();
+        tree.finish_expand(item.clone(), futures::executor::block_on(job));
+
+        assert_eq!(tree.count(), 2);
+        assert!(item
+            .flags()
+            .contains(TreeFlags::EXPANDED | TreeFlags::READY));
+
+        let leaf = tree.find(11).unwrap();
+        let job = tree.provider.expand(&leaf).into_job().unwrap();
+        assert!(matches!(
+            futures::executor::block_on(job),
+            Err(ProviderError::Message(message)) if message == "not found"
+        ));
+    }
+
+    #[test]
+    fn collapse_cancels_load() {
+        let root = expanded_root()
+            .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]);
+        let tree = DynamicTree::new(root, Default::default());
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        let item = tree.find(1).unwrap();
+        let stale = tree.begin_load(&item);
+        tree.flatten();
+        assert_eq!(tree.count(), 2);
+
+        // collapsed while the load is in flight
+        assert!(TreeController::collapse(&*tree, 1));
+        assert_eq!(tree.count(), 1);
+        assert!(!item.flags().contains(TreeFlags::LOADING));
+
+        tree.finish_load(item.clone(), stale, Ok(vec![node(&item, 11, "1.1")]));
+        assert_eq!(item.children_len(), 0);
+        assert!(!item
+            .flags()
+            .intersects(TreeFlags::EXPANDED | TreeFlags::LOADING | TreeFlags::READY));
+        assert_eq!(recorder.update_all.get(), 1);
+
+        // a newer load of the same node wins over the older one
+        let first = tree.begin_load(&item);
+        tree.cancel_load(&item);
+        let second = tree.begin_load(&item);
+
+        tree.finish_load(item.clone(), first, Ok(vec![node(&item, 11, "1.1")]));
+        assert!(item.flags().contains(TreeFlags::LOADING));
+        assert_eq!(item.children_len(), 0);
+
+        tree.finish_load(item.clone(), second, Ok(vec![node(&item, 12, "1.2")]));
+        assert!(!item.flags().contains(TreeFlags::LOADING));
+        assert_eq!(tree.count(), 2);
+        assert!(tree.find(12).is_some());
+    }
+
+    struct FlakyProvider {
+        root: Rc<TreeNode>,
+        attempts: Cell<usize>,
+    }
+
+    impl AsyncTreeProvider for FlakyProvider {
+        fn root(&self) -> Rc<TreeNode> {
+            self.root.clone()
+        }
+
+        fn expand(
+            &self,
+            parent: &Rc<TreeNode>,
+        ) -> LocalBoxFuture<'static, Result<Vec<Rc<TreeNode>>, ProviderError>> {
+            self.attempts.set(self.attempts.get() + 1);
+            let first = self.attempts.get() == 1;
+            let parent = parent.clone();
+            async move {
+                if first {
+                    Err(ProviderError::Message("offline".into()))
+                } else {
+                    Ok(vec![node(&parent, 11, "1.1")])
+                }
+            }
+            .boxed_local()
+        }
+    }
+
+    // What `load` does, awaiting the job in place of `spawn_local`. Returns
+    // the error the load failed with
+    fn load_now(tree: &DynamicTree, key: KeyType) -> Option<ProviderError> {
+        let item = tree.find(key).unwrap();
+        let job = tree.provider.expand(&item).into_job().unwrap();
+        let generation = tree.begin_load(&item);
+        let result = futures::executor::block_on(job);
+        let err = result.as_ref().err().cloned();
+        tree.finish_load(item, generation, result);
+        err
+    }
+
+    #[test]
+    fn retry_failed_load() {
+        let provider = Rc::new(FlakyProvider {
+            root: expanded_root()
+                .build(|root| vec![node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)]),
+            attempts: Cell::new(0),
+        });
+        let tree = DynamicTree::new(provider.clone(), Default::default());
+        let item = tree.find(1).unwrap();
+
+        assert!(matches!(
+            load_now(&tree, 1),
+            Some(ProviderError::Message(message)) if message == "offline"
+        ));
+        assert!(item.error());
+        assert!(!item
+            .flags()
+            .intersects(TreeFlags::LOADING | TreeFlags::READY | TreeFlags::EXPANDED));
+        assert_eq!(tree.count(), 1);
+
+        assert!(load_now(&tree, 1).is_none());
+        assert_eq!(provider.attempts.get(), 2);
+        assert!(!item.error());
+        assert!(item
+            .flags()
+            .contains(TreeFlags::READY | TreeFlags::EXPANDED));
+        assert_eq!(tree.count(), 2);
+    }
+
+    #[test]
+    fn icon_swaps_at_runtime() {
+        let folder = |icon: Option<&'static str>| {
+            Box::new(PlainTreeData {
+                key: 1,
+                icon: icon.map(Into::into),
+                title: "src".into(),
+                flags: TreeFlags::EXPANDABLE,
+            })
+        };
+
+        let root = expanded_root().build(|root| {
+            vec![TreeNode::new(root, folder(Some("folder")))
+                .build(|parent| vec![node(parent, 11, "1.1")])]
+        });
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        let recorder = Rc::new(Recorder::default());
+        tree.add_subscriber(recorder.clone());
+
+        // e.g. from `on_expand`
+        tree.expand(1);
+        assert!(tree.set_data_if_changed(1, folder(Some("folder-open"))));
+        assert_eq!(tree.item(0).icon().as_deref(), Some("folder-open"));
+        assert_eq!(recorder.update_item.get(), 1);
+
+        assert!(tree.set_data_if_changed(1, folder(None)));
+        assert!(tree.item(0).icon().is_none());
+        assert!(!tree.set_data_if_changed(1, folder(None)));
+    }
+
+    #[test]
+    fn toggle_splices_rows() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", folder)
+                            .build(|parent| vec![node(parent, 111, "1.1.1")]),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node_with_flags(root, 2, "2", folder).build(|parent| vec![node(parent, 21, "2.1")]),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let keys = || tree.flat.borrow().keys().copied().collect::<Vec<_>>();
+        let rebuilt = || tree.root.flatten().keys().copied().collect::<Vec<_>>();
+
+        for key in [2, 1, 11, 1, 2, 1] {
+            tree.expand(key);
+            assert_eq!(keys(), rebuilt());
+        }
+
+        assert_eq!(keys(), [1, 11, 111, 12, 2]);
+        assert_eq!(tree.index_of(2), Some(4));
     }
 }
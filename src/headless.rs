@@ -0,0 +1,173 @@
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::dynamic::TreeSubscriber;
+use super::view::TreeController;
+use super::KeyType;
+
+// A row as the headless view sees it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessRow {
+    pub key: KeyType,
+    pub title: String,
+    pub depth: u16,
+    pub expanded: bool,
+}
+
+// A view without a DOM. It keeps a window of the visible rows in sync with
+// the controller and records every notification it gets, so the tree logic
+// can be exercised natively or rendered on the server
+pub struct HeadlessView {
+    ctrl: Rc<dyn TreeController>,
+    range: RefCell<Range<usize>>,
+    rows: RefCell<Vec<HeadlessRow>>,
+    update_all: Cell<usize>,
+    updated_keys: RefCell<Vec<KeyType>>,
+}
+
+impl HeadlessView {
+    pub fn render(ctrl: Rc<dyn TreeController>, range: Range<usize>) -> Rc<HeadlessView> {
+        let view = Rc::new(HeadlessView {
+            ctrl,
+            range: RefCell::new(range),
+            rows: RefCell::new(vec![]),
+            update_all: Cell::new(0),
+            updated_keys: RefCell::new(vec![]),
+        });
+
+        view.refresh();
+        view.ctrl.add_subscriber(view.clone());
+        view
+    }
+
+    pub fn set_range(&self, range: Range<usize>) {
+        self.range.replace(range);
+        self.refresh();
+    }
+
+    pub fn rows(&self) -> Vec<HeadlessRow> {
+        self.rows.borrow().clone()
+    }
+
+    pub fn titles(&self) -> Vec<String> {
+        self.rows
+            .borrow()
+            .iter()
+            .map(|row| row.title.clone())
+            .collect()
+    }
+
+    // Number of `update_all` notifications so far
+    pub fn update_all_count(&self) -> usize {
+        self.update_all.get()
+    }
+
+    // Keys of the single item notifications since the last call
+    pub fn take_updated_keys(&self) -> Vec<KeyType> {
+        self.updated_keys.take()
+    }
+
+    fn refresh(&self) {
+        let range = self.range.borrow().clone();
+        let end = range.end.min(self.ctrl.count());
+        let rows = (range.start.min(end)..end)
+            .map(|index| self.row(index))
+            .collect();
+
+        self.rows.replace(rows);
+    }
+
+    fn row(&self, index: usize) -> HeadlessRow {
+        let item = self.ctrl.item(index);
+        let title = item.title().to_string();
+        HeadlessRow {
+            key: item.key(),
+            title,
+            depth: item.depth(),
+            expanded: item.expanded(),
+        }
+    }
+}
+
+impl TreeSubscriber for HeadlessView {
+    fn update_all(&self) {
+        self.update_all.set(self.update_all.get() + 1);
+        self.refresh();
+    }
+
+    fn update_item(&self, key: KeyType) {
+        self.updated_keys.borrow_mut().push(key);
+
+        let Some(index) = self.ctrl.index_of(key) else {
+            return;
+        };
+
+        let start = self.range.borrow().start;
+        let row = self.row(index);
+        if let Some(slot) = self.rows.borrow_mut().get_mut(index.wrapping_sub(start)) {
+            *slot = row;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::DynamicTree;
+    use crate::node::tests::{expanded_root, node, node_with_flags};
+    use crate::node::TreeFlags;
+
+    fn sample() -> Rc<DynamicTree> {
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", TreeFlags::EXPANDABLE)
+                    .build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")]),
+                node(root, 2, "2"),
+                node(root, 3, "3"),
+            ]
+        });
+
+        DynamicTree::new(root, Default::default())
+    }
+
+    #[test]
+    fn follows_expansion() {
+        let tree = sample();
+        let view = HeadlessView::render(tree.clone(), 0..3);
+        assert_eq!(view.titles(), ["1", "2", "3"]);
+
+        tree.expand(1);
+        assert_eq!(view.titles(), ["1", "1.1", "1.2"]);
+        assert_eq!(view.update_all_count(), 1);
+        assert_eq!(
+            view.rows()[1],
+            HeadlessRow {
+                key: 11,
+                title: "1.1".into(),
+                depth: 2,
+                expanded: false,
+            }
+        );
+
+        view.set_range(3..10);
+        assert_eq!(view.titles(), ["2", "3"]);
+
+        tree.collapse(1);
+        assert!(view.titles().is_empty());
+    }
+
+    #[test]
+    fn records_item_updates() {
+        let tree = sample();
+        tree.set_sync_updates(true);
+        let view = HeadlessView::render(tree.clone(), 0..2);
+
+        tree.set_load_progress(2, Some(0.5));
+        tree.set_load_progress(3, Some(0.5));
+
+        assert_eq!(view.take_updated_keys(), [2, 3]);
+        assert!(view.take_updated_keys().is_empty());
+        assert_eq!(view.update_all_count(), 0);
+    }
+}
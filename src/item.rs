@@ -1,17 +1,109 @@
 use std::cell::Ref;
+use std::ops::Range;
 
 use super::{HashType, KeyType};
 
 pub trait TreeItem {
     fn key(&self) -> KeyType;
-    fn icon(&self) -> Ref<str>;
-    fn title(&self) -> Ref<str>;
+    // `None` hides the icon element
+    fn icon(&self) -> Option<Ref<'_, str>>;
+    // Shown in place of `icon` while the item is expanded
+    fn icon_expanded(&self) -> Option<Ref<'_, str>> {
+        None
+    }
+    fn title(&self) -> Ref<'_, str>;
+    // Set as the `title` attribute of the row, checked whenever `hash`
+    // changes
+    fn tooltip(&self) -> Option<Ref<'_, str>> {
+        None
+    }
     fn depth(&self) -> u16;
     fn expandable(&self) -> bool {
         false
     }
     fn hash(&self) -> HashType;
+    fn title_hash(&self) -> HashType {
+        self.hash()
+    }
+    fn icon_hash(&self) -> HashType {
+        self.hash()
+    }
+    fn detail_hash(&self) -> HashType {
+        self.hash()
+    }
     fn expanded(&self) -> bool {
         false
     }
+    fn is_group_header(&self) -> bool {
+        false
+    }
+    fn visible_children_len(&self) -> usize {
+        0
+    }
+    fn check_state(&self) -> CheckState {
+        CheckState::Unchecked
+    }
+    // Rows with a pending load render a spinner
+    fn loading(&self) -> bool {
+        false
+    }
+    // Rows whose children failed to load show an error state
+    fn error(&self) -> bool {
+        false
+    }
+    // Determinate progress of a pending load, from 0.0 to 1.0
+    fn load_progress(&self) -> Option<f32> {
+        None
+    }
+    // Byte ranges of the title to highlight, e.g. the matched part of a
+    // search query
+    fn title_ranges(&self) -> Option<Vec<Range<usize>>> {
+        None
+    }
+    // Rows are laid out with this height instead of the view's
+    // `item_height`, the row content is expected to fill it
+    fn height(&self) -> Option<usize> {
+        None
+    }
+    // One based position among the siblings and the number of siblings
+    fn set_position(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+// The icon a row shows for the current expansion state
+pub fn displayed_icon(item: &dyn TreeItem) -> Option<Ref<'_, str>> {
+    if item.expanded() {
+        if let Some(icon) = item.icon_expanded() {
+            return Some(icon);
+        }
+    }
+
+    item.icon()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckState {
+    Unchecked,
+    Checked,
+    Indeterminate,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ItemHashes {
+    pub hash: HashType,
+    pub title: HashType,
+    pub icon: HashType,
+    pub detail: HashType,
+}
+
+impl ItemHashes {
+    pub fn of(item: &dyn TreeItem) -> Self {
+        ItemHashes {
+            hash: item.hash(),
+            title: item.title_hash(),
+            icon: item.icon_hash(),
+            detail: item.detail_hash(),
+        }
+    }
 }
@@ -6,17 +6,45 @@ use super::node::TreeNode;
 
 pub struct TreeNodeIterator {
 	range: Range<usize>,
+	yielded: usize,
 	stack: Vec<TreeCursor>,
 }
 
 impl TreeNodeIterator {
 	pub fn new(root: Rc<TreeNode>, range: Range<usize>) -> Self {
-		let stack = root.find_by_index(range.start);
-		TreeNodeIterator { range, stack }
+		// Ranges past the visible rows yield nothing instead of looking up a
+		// start index that doesn't exist
+		let stack = if range.is_empty() || range.start >= root.visible_children_len() {
+			Vec::new()
+		} else {
+			root.find_by_index(range.start)
+		};
+
+		TreeNodeIterator {
+			range,
+			yielded: 0,
+			stack,
+		}
 	}
+}
+
+impl Iterator for TreeNodeIterator {
+	type Item = Rc<TreeNode>;
+
+	fn next(&mut self) -> Option<Rc<TreeNode>> {
+		if self.yielded >= self.range.len() {
+			return None;
+		}
+
+		self.yielded += 1;
 
-	pub fn next(&mut self) -> Option<Rc<TreeNode>> {
-		let Some(frame) = self.stack.last().cloned() else { return None };
+		let frame = self.stack.last().cloned()?;
+
+		// a loading node is followed by its placeholder row only
+		if frame.node.is_loading() {
+			self.stack.push(TreeCursor::placeholder(&frame.node));
+			return Some(frame.node);
+		}
 
 		// first check if we can go down to children
 		if frame.node.expanded() {
@@ -32,7 +60,7 @@ impl TreeNodeIterator {
 
 		// then check if we have a next sibling
 		if let Some(next_sibling) = frame.next_sibling() {
-			self.stack.push(next_sibling);
+			*self.stack.last_mut().unwrap() = next_sibling;
 			return Some(frame.node);
 		}
 
@@ -59,23 +87,179 @@ pub struct TreeCursor {
 }
 
 impl TreeCursor {
+	// Placeholders aren't among the children, so they never have siblings
+	pub fn placeholder(node: &Rc<TreeNode>) -> TreeCursor {
+		TreeCursor {
+			node: node.placeholder(),
+			offset: usize::MAX,
+		}
+	}
+
 	pub fn next_sibling(&self) -> Option<TreeCursor> {
-		let parent = self.node.parent.upgrade().unwrap();
-		let next_offset = self.offset + 1;
+		let parent = self.node.parent()?;
+		let next_offset = self.offset.checked_add(1)?;
 
-		if let Some(next_sibling) = parent
+		let next_sibling = parent
 			.inner()
 			.children
 			.get_index(next_offset)
 			.map(|v| v.1)
-			.cloned()
-		{
-			Some(TreeCursor {
-				node: next_sibling,
-				offset: next_offset,
-			});
+			.cloned()?;
+
+		Some(TreeCursor {
+			node: next_sibling,
+			offset: next_offset,
+		})
+	}
+}
+
+// Yields visible nodes in order together with whether the node and each of
+// its ancestors is the last child on its level, used to draw connectors
+pub struct ConnectorIterator {
+	stack: Vec<(Rc<TreeNode>, usize)>,
+	last: Vec<bool>,
+}
+
+impl ConnectorIterator {
+	pub fn new(root: Rc<TreeNode>) -> Self {
+		ConnectorIterator {
+			stack: vec![(root, 0)],
+			last: Vec::new(),
+		}
+	}
+}
+
+impl Iterator for ConnectorIterator {
+	type Item = (Rc<TreeNode>, Vec<bool>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let (parent, index) = self.stack.last_mut()?;
+			let (child, len) = {
+				let inner = parent.inner();
+				let child = inner.children.get_index(*index).map(|v| v.1.clone());
+				(child, inner.children.len())
+			};
+
+			let Some(child) = child else {
+				self.stack.pop();
+				continue;
+			};
+
+			*index += 1;
+			let is_last = *index == len;
+
+			self.last.truncate(self.stack.len() - 1);
+			self.last.push(is_last);
+
+			let item = (child.clone(), self.last.clone());
+			if child.expanded() && child.first_child().is_some() {
+				self.stack.push((child, 0));
+			}
+
+			return Some(item);
 		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::node::tests::{expanded_root, node, node_with_flags};
+	use crate::node::TreeFlags;
+
+	fn titles(iter: TreeNodeIterator) -> Vec<String> {
+		iter.map(|node| node.title().to_string()).collect()
+	}
+
+	#[test]
+	fn siblings_under_root() {
+		let root = expanded_root()
+			.build(|root| vec![node(root, 1, "1"), node(root, 2, "2"), node(root, 3, "3")]);
+
+		assert_eq!(titles(root.slice(0..3)), ["1", "2", "3"]);
+	}
+
+	#[test]
+	fn slice_is_bounded() {
+		let root = expanded_root().build(|root| {
+			(1..=5)
+				.map(|key| node(root, key, &key.to_string()))
+				.collect()
+		});
+
+		let slice = root.slice(1..3).collect::<Vec<_>>();
+		assert_eq!(slice.len(), 2);
+		assert_eq!(&*slice[0].title(), "2");
+		assert_eq!(&*slice[1].title(), "3");
+	}
+
+	#[test]
+	fn siblings_in_folder() {
+		let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+		let root = expanded_root().build(|root| {
+			vec![
+				node_with_flags(root, 1, "1", folder).build(|parent| {
+					vec![
+						node(parent, 11, "1.1"),
+						node(parent, 12, "1.2"),
+						node(parent, 13, "1.3"),
+					]
+				}),
+				node(root, 2, "2"),
+			]
+		});
+
+		assert_eq!(titles(root.slice(0..5)), ["1", "1.1", "1.2", "1.3", "2"]);
+
+		let cursor = TreeCursor {
+			node: root.get(2).unwrap(),
+			offset: 1,
+		};
+		assert!(cursor.next_sibling().is_none());
+	}
+
+	#[test]
+	fn connectors() {
+		let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+		let root = expanded_root().build(|root| {
+			vec![
+				node_with_flags(root, 1, "1", folder)
+					.build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")]),
+				node_with_flags(root, 2, "2", folder).build(|parent| vec![node(parent, 21, "2.1")]),
+				node_with_flags(root, 3, "3", TreeFlags::EXPANDABLE)
+					.build(|parent| vec![node(parent, 31, "3.1")]),
+			]
+		});
+
+		let connectors = root
+			.connectors()
+			.map(|(node, last)| (node.key(), last))
+			.collect::<Vec<_>>();
+
+		assert_eq!(
+			connectors,
+			[
+				(1, vec![false]),
+				(11, vec![false, false]),
+				(12, vec![false, true]),
+				(2, vec![false]),
+				(21, vec![false, true]),
+				(3, vec![true]),
+			]
+		);
+	}
+
+	#[test]
+	fn slice_edge_cases() {
+		let root = expanded_root().build(|root| {
+			(1..=3)
+				.map(|key| node(root, key, &key.to_string()))
+				.collect()
+		});
 
-		None
+		assert!(titles(root.slice(2..2)).is_empty());
+		assert_eq!(titles(root.slice(1..10)), ["2", "3"]);
+		assert!(titles(root.slice(5..8)).is_empty());
 	}
 }
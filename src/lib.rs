@@ -1,13 +1,16 @@
-#![feature(hash_extract_if)]
-#![feature(extract_if)]
 
+pub mod derive;
 pub mod dynamic;
+pub mod headless;
 pub mod item;
 pub mod iter;
 pub mod node;
+pub mod offsets;
+pub mod placeholder;
 pub mod plain;
 pub mod provider;
 pub mod root;
+pub mod spec;
 pub mod view;
 
 pub type KeyType = usize;
@@ -1,11 +1,13 @@
 use std::cell::{Ref, RefCell, RefMut};
+use std::cmp::Ordering;
 use std::ops::Range;
 use std::rc::{Rc, Weak};
 
 use indexmap::IndexMap;
 
-use super::item::TreeItem;
-use super::iter::{TreeCursor, TreeNodeIterator};
+use super::item::{CheckState, TreeItem};
+use super::iter::{ConnectorIterator, TreeCursor, TreeNodeIterator};
+use super::placeholder::PlaceholderData;
 use super::provider::{TreeExpandResult, TreeProvider};
 use super::root::RootData;
 use super::{HashType, KeyType};
@@ -23,6 +25,31 @@ pub trait TreeData: downcast_rs::Downcast + std::fmt::Debug {
     fn title(&self) -> &str;
     fn hash(&self) -> HashType;
     fn flags(&self) -> TreeFlags;
+
+    // Per-field hashes let the renderer touch only the changed parts of a
+    // row, by default any change updates all of them
+    fn title_hash(&self) -> HashType {
+        self.hash()
+    }
+    fn icon_hash(&self) -> HashType {
+        self.hash()
+    }
+    fn detail_hash(&self) -> HashType {
+        self.hash()
+    }
+    fn height(&self) -> Option<usize> {
+        None
+    }
+    // Icon of an expanded folder, falls back to `icon`. It is expected to be
+    // part of `icon_hash`
+    fn icon_expanded(&self) -> Option<&str> {
+        None
+    }
+    // Native tooltip of the row, `None` shows none. A tooltip that isn't
+    // the title is expected to be part of `hash`
+    fn tooltip(&self) -> Option<&str> {
+        Some(self.title())
+    }
 }
 
 downcast_rs::impl_downcast!(TreeData);
@@ -35,20 +62,65 @@ bitflags::bitflags! {
         const LOADING = 0b00000100;
         const READY = 0b00001000;
         const EXPANDABLE = 0b00010000;
+        const GROUP_HEADER = 0b00100000;
+        const UNRESOLVED = 0b01000000;
+        const CHECKED = 0b10000000;
+        // Some descendants are checked and some are not
+        const INDETERMINATE = 0b1_00000000;
+        // The last load of the children failed, expanding retries it
+        const FAILED = 0b10_00000000;
     }
 }
 
+// Depth saturates at this value instead of wrapping, so nodes nested
+// deeper than that share the same indentation
+pub const MAX_DEPTH: u16 = u16::MAX;
+
 #[derive(Debug)]
 pub struct TreeNodeInner {
     pub(crate) data: Box<dyn TreeData>,
     depth: u16,
     flags: TreeFlags,
     children_len: usize,
+    load_progress: Option<f32>,
+    title_ranges: Option<Vec<Range<usize>>>,
+    // Visible descendants as if the node was expanded, `None` once the
+    // subtree changed
+    flat_cache: Option<Rc<FlatRows>>,
+    placeholder: Option<Rc<TreeNode>>,
     pub(crate) children: IndexMap<KeyType, Rc<TreeNode>>,
 }
 
+// Visible rows below a node. The rows of expanded children are shared with
+// their own caches rather than copied, so a change only rebuilds the lists
+// of its ancestors and every row is stored once
+#[derive(Debug)]
+struct FlatRows {
+    len: usize,
+    segments: Vec<FlatSegment>,
+}
+
+#[derive(Debug)]
+enum FlatSegment {
+    Row(Rc<TreeNode>),
+    Rows(Rc<FlatRows>),
+}
+
+impl FlatRows {
+    fn extend_into(&self, list: &mut IndexMap<KeyType, Rc<TreeNode>>) {
+        for segment in &self.segments {
+            match segment {
+                FlatSegment::Row(node) => {
+                    list.insert(node.key(), node.clone());
+                }
+                FlatSegment::Rows(rows) => rows.extend_into(list),
+            }
+        }
+    }
+}
+
 pub struct TreeNode {
-    pub(crate) parent: Weak<TreeNode>,
+    parent: RefCell<Weak<TreeNode>>,
     inner: RefCell<TreeNodeInner>,
 }
 
@@ -60,15 +132,25 @@ impl std::fmt::Debug for TreeNode {
 
 impl TreeNode {
     pub fn new(parent: &Rc<TreeNode>, data: Box<dyn TreeData>) -> Rc<Self> {
+        let mut flags = data.flags();
+        // pre-expanded nodes come with their children already loaded
+        if flags.contains(TreeFlags::EXPANDED) {
+            flags.insert(TreeFlags::READY);
+        }
+
         Rc::new(TreeNode {
             inner: RefCell::new(TreeNodeInner {
-                flags: data.flags(),
+                flags,
                 data,
-                depth: parent.depth() + 1,
+                depth: parent.depth().saturating_add(1),
                 children: Default::default(),
                 children_len: 0,
+                load_progress: None,
+                title_ranges: None,
+                flat_cache: None,
+                placeholder: None,
             }),
-            parent: Rc::downgrade(parent),
+            parent: RefCell::new(Rc::downgrade(parent)),
         })
     }
 
@@ -76,14 +158,44 @@ impl TreeNode {
         Self::root_with_data(Box::new(RootData))
     }
 
+    // A hidden root over several top level nodes, e.g. "Workspace" and "Open
+    // Editors", each of them expands on its own. The nodes are moved over
+    // from whatever parent they were built with and end up at depth 1 like
+    // any other top level node
+    pub fn forest(roots: Vec<Rc<TreeNode>>) -> Rc<Self> {
+        let root = Self::root();
+        root.set_flags(root.flags() | TreeFlags::EXPANDED | TreeFlags::READY);
+
+        for node in roots {
+            node.move_to(&root, usize::MAX);
+        }
+
+        root
+    }
+
     pub fn clear(&self) {
-        let mut inner = self.inner.borrow_mut();
-        let mut flags = inner.flags;
-        flags.remove(TreeFlags::EXPANDED);
-        flags.remove(TreeFlags::READY);
-        inner.flags = flags;
-        inner.children.clear();
-        inner.children_len = 0;
+        {
+            let mut inner = self.inner.borrow_mut();
+            let mut flags = inner.flags;
+            flags.remove(TreeFlags::EXPANDED);
+            flags.remove(TreeFlags::READY);
+            inner.flags = flags;
+        }
+
+        self.clear_children();
+    }
+
+    // Unlike `clear`, keeps the node flags intact
+    pub fn clear_children(&self) {
+        let removed_len = {
+            let mut inner = self.inner.borrow_mut();
+            inner.children.clear();
+            inner.flat_cache = None;
+            std::mem::take(&mut inner.children_len)
+        };
+
+        self.update_ancestors_len(|len| *len -= removed_len);
+        self.invalidate_ancestors();
     }
 
     pub fn root_with_data(data: Box<dyn TreeData>) -> Rc<Self> {
@@ -94,39 +206,89 @@ impl TreeNode {
                 children: Default::default(),
                 depth: 0,
                 children_len: 0,
+                load_progress: None,
+                title_ranges: None,
+                flat_cache: None,
+                placeholder: None,
             }),
-            parent: this.clone(),
+            parent: RefCell::new(this.clone()),
         })
     }
 
-    pub fn inner(&self) -> Ref<TreeNodeInner> {
+    pub fn parent(&self) -> Option<Rc<TreeNode>> {
+        self.parent.borrow().upgrade()
+    }
+
+    pub fn inner(&self) -> Ref<'_, TreeNodeInner> {
         self.inner.borrow()
     }
 
-    pub fn inner_mut(&self) -> RefMut<TreeNodeInner> {
+    pub fn inner_mut(&self) -> RefMut<'_, TreeNodeInner> {
         self.inner.borrow_mut()
     }
 
     pub fn flags(&self) -> TreeFlags {
-        self.inner.borrow_mut().flags
+        self.inner.borrow().flags
     }
 
     pub fn set_flags(&self, flags: TreeFlags) {
-        self.inner.borrow_mut().flags = flags;
+        let old = std::mem::replace(&mut self.inner.borrow_mut().flags, flags);
+        let visibility = TreeFlags::EXPANDED | TreeFlags::LOADING;
+        if old & visibility != flags & visibility {
+            self.invalidate_ancestors();
+        }
+    }
+
+    pub fn set_load_progress(&self, progress: Option<f32>) {
+        self.inner.borrow_mut().load_progress = progress;
+    }
+
+    pub fn set_title_ranges(&self, ranges: Option<Vec<Range<usize>>>) {
+        self.inner.borrow_mut().title_ranges = ranges;
     }
 
     pub fn get(&self, key: KeyType) -> Option<Rc<TreeNode>> {
         self.inner_mut().children.get(&key).cloned()
     }
 
-    pub fn title(&self) -> Ref<str> {
+    pub fn title(&self) -> Ref<'_, str> {
         Ref::map(self.inner.borrow(), |v| v.data.title())
     }
 
-    pub fn data(&self) -> Ref<dyn TreeData> {
+    pub fn data(&self) -> Ref<'_, dyn TreeData> {
         Ref::map(self.inner.borrow(), |v| &*v.data)
     }
 
+    // Typed access for trees that store a single kind of data, `None` if
+    // the node holds something else
+    pub fn data_as<D: TreeData>(&self) -> Option<Ref<'_, D>> {
+        Ref::filter_map(self.inner.borrow(), |v| v.data.downcast_ref::<D>()).ok()
+    }
+
+    pub fn set_data(&self, data: Box<dyn TreeData>) {
+        self.inner_mut().data = data;
+    }
+
+    // Field hashes are compared as well, data like `PlainTreeData` doesn't
+    // include the icon in its main hash
+    pub fn set_data_if_changed(&self, data: Box<dyn TreeData>) -> bool {
+        let hashes = |data: &dyn TreeData| {
+            (
+                data.hash(),
+                data.title_hash(),
+                data.icon_hash(),
+                data.detail_hash(),
+            )
+        };
+
+        if hashes(&*self.inner().data) == hashes(&*data) {
+            return false;
+        }
+
+        self.set_data(data);
+        true
+    }
+
     pub fn children_len(&self) -> usize {
         self.inner.borrow().children_len
     }
@@ -140,33 +302,211 @@ impl TreeNode {
         self.insert_inner(children, true);
     }
 
+    // An `index` past the last child appends
+    pub fn insert_at(&self, index: usize, children: Vec<Rc<TreeNode>>) {
+        self.insert_at_inner(index, children, true);
+    }
+
+    // Children are assumed to be already sorted by `cmp`
+    pub fn insert_sorted(
+        &self,
+        child: Rc<TreeNode>,
+        cmp: impl Fn(&Rc<TreeNode>, &Rc<TreeNode>) -> Ordering,
+    ) {
+        let index = {
+            let inner = self.inner();
+            let (mut low, mut high) = (0, inner.children.len());
+            while low < high {
+                let mid = (low + high) / 2;
+                let (_, existing) = inner.children.get_index(mid).unwrap();
+                if cmp(existing, &child) == Ordering::Greater {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+
+            low
+        };
+
+        self.insert_at_inner(index, vec![child], true);
+    }
+
+    pub fn remove_child(&self, key: KeyType) -> Option<Rc<TreeNode>> {
+        let (child, removed_len) = {
+            let mut inner = self.inner_mut();
+            let child = inner.children.shift_remove(&key)?;
+            let removed_len = 1 + child.children_len();
+            inner.children_len -= removed_len;
+            inner.flat_cache = None;
+            (child, removed_len)
+        };
+
+        self.update_ancestors_len(|len| *len -= removed_len);
+        self.invalidate_ancestors();
+        Some(child)
+    }
+
+    pub fn remove_self(self: &Rc<Self>) -> bool {
+        match self.parent() {
+            Some(parent) if !self.is_root() => parent.remove_child(self.key()).is_some(),
+            _ => false,
+        }
+    }
+
+    // Detaches the node with its subtree and inserts it under `new_parent`,
+    // the caller makes sure `new_parent` isn't inside the subtree
+    pub fn move_to(self: &Rc<Self>, new_parent: &Rc<TreeNode>, index: usize) {
+        if let Some(parent) = self.parent() {
+            parent.remove_child(self.key());
+        }
+
+        self.parent.replace(Rc::downgrade(new_parent));
+        self.recompute_depth(new_parent.depth());
+        new_parent.insert_at(index, vec![self.clone()]);
+    }
+
+    // Depth is stored rather than computed, so grafted subtrees need it
+    // to be updated from the new parent down
+    pub fn recompute_depth(self: &Rc<Self>, parent_depth: u16) {
+        let depth = parent_depth.saturating_add(1);
+        self.inner_mut().depth = depth;
+
+        let inner = self.inner();
+        let children = inner.children.values().chain(&inner.placeholder);
+        let children = children.cloned().collect::<Vec<_>>();
+        drop(inner);
+
+        for child in children {
+            child.recompute_depth(depth);
+        }
+    }
+
+    // Parents from the closest one upwards, the root is not included
+    pub fn ancestors(self: &Rc<Self>) -> Vec<Rc<TreeNode>> {
+        let mut ancestors = Vec::new();
+        if self.is_root() {
+            return ancestors;
+        }
+
+        let mut parent = self.parent();
+        while let Some(node) = parent {
+            if node.is_root() {
+                break;
+            }
+
+            parent = node.parent();
+            ancestors.push(node);
+        }
+
+        ancestors
+    }
+
+    // Keys from the top level down to this node
+    pub fn path_keys(self: &Rc<Self>) -> Vec<KeyType> {
+        let mut keys = self
+            .ancestors()
+            .iter()
+            .map(|node| node.key())
+            .collect::<Vec<_>>();
+
+        keys.reverse();
+        if !self.is_root() {
+            keys.push(self.key());
+        }
+
+        keys
+    }
+
+    // Whether `self` is `node` or one of its descendants
+    pub fn is_within(self: &Rc<Self>, node: &Rc<TreeNode>) -> bool {
+        let mut current = Some(self.clone());
+        while let Some(next) = current {
+            if Rc::ptr_eq(&next, node) {
+                return true;
+            }
+
+            if next.is_root() {
+                break;
+            }
+
+            current = next.parent();
+        }
+
+        false
+    }
+
     fn insert_inner(&self, children: Vec<Rc<TreeNode>>, update_parent: bool) {
+        self.insert_at_inner(usize::MAX, children, update_parent)
+    }
+
+    fn insert_at_inner(&self, index: usize, children: Vec<Rc<TreeNode>>, update_parent: bool) {
         let combined_len = children
             .iter()
             .fold(0, |a, b| a + 1 + b.inner().children_len);
 
         let mut self_mut = self.inner_mut();
 
+        let tail = if index < self_mut.children.len() {
+            self_mut.children.drain(index..).collect()
+        } else {
+            Vec::new()
+        };
+
         self_mut.children.extend(children.into_iter().map(|c| {
             let key = { c.inner().data.key() };
+            debug_assert!(
+                !PlaceholderData::is_reserved(key),
+                "key {key} is reserved for placeholder rows"
+            );
             (key, c)
         }));
 
+        self_mut.children.extend(tail);
         self_mut.children_len += combined_len;
+        self_mut.flat_cache = None;
+        drop(self_mut);
 
-        if self_mut.flags.contains(TreeFlags::ROOT) || !update_parent {
+        if update_parent {
+            self.update_ancestors_len(|len| *len += combined_len);
+        }
+
+        self.invalidate_ancestors();
+    }
+
+    fn update_ancestors_len(&self, func: impl Fn(&mut usize)) {
+        if self.is_root() {
             return;
         }
 
-        let mut parent = self.parent.upgrade();
+        let mut parent = self.parent();
         while let Some(node) = parent {
-            node.inner_mut().children_len += combined_len;
+            func(&mut node.inner_mut().children_len);
 
             if node.is_root() {
                 break;
             }
 
-            parent = node.parent.upgrade();
+            parent = node.parent();
+        }
+    }
+
+    // Ancestor caches include this subtree, so they have to be rebuilt
+    // whenever it changes or the node gets expanded or collapsed
+    fn invalidate_ancestors(&self) {
+        if self.is_root() {
+            return;
+        }
+
+        let mut parent = self.parent();
+        while let Some(node) = parent {
+            node.inner_mut().flat_cache = None;
+
+            if node.is_root() {
+                break;
+            }
+
+            parent = node.parent();
         }
     }
 
@@ -178,71 +518,187 @@ impl TreeNode {
         self
     }
 
+    // Searches the whole subtree, including collapsed nodes
+    pub fn find(self: &Rc<Self>, key: KeyType) -> Option<Rc<TreeNode>> {
+        if self.key() == key {
+            return Some(self.clone());
+        }
+
+        self.inner()
+            .children
+            .values()
+            .find_map(|child| child.find(key))
+    }
+
     pub fn is_root(&self) -> bool {
         self.flags().contains(TreeFlags::ROOT)
     }
 
+    // Visits every descendant in depth-first order, including collapsed ones
+    pub fn walk(&self, func: &mut impl FnMut(&Rc<TreeNode>)) {
+        // `func` may update flags, which needs to borrow this node again
+        let children = self.inner().children.values().cloned().collect::<Vec<_>>();
+        for child in &children {
+            func(child);
+            child.walk(func);
+        }
+    }
+
+    // A node that is loading its children shows a single placeholder row
+    // instead of them. The root is excluded, the view shows its own
+    // indicator for it
+    pub fn is_loading(&self) -> bool {
+        !self.is_root() && self.flags().contains(TreeFlags::LOADING)
+    }
+
+    pub fn placeholder(self: &Rc<TreeNode>) -> Rc<TreeNode> {
+        if let Some(placeholder) = &self.inner().placeholder {
+            return placeholder.clone();
+        }
+
+        let data = PlaceholderData {
+            key: PlaceholderData::key_for(self.key()),
+        };
+
+        let placeholder = TreeNode::new(self, Box::new(data));
+        self.inner_mut().placeholder = Some(placeholder.clone());
+        placeholder
+    }
+
     pub fn flatten(self: &Rc<TreeNode>) -> IndexMap<KeyType, Rc<TreeNode>> {
         let mut list = IndexMap::with_capacity(self.children_len() + 1);
-        self.flatten_internal(&mut list);
+        if !self.is_root() {
+            list.insert(self.key(), self.clone());
+        }
+
+        if self.is_loading() {
+            let placeholder = self.placeholder();
+            list.insert(placeholder.key(), placeholder);
+        } else if self.flags().contains(TreeFlags::EXPANDED) {
+            self.visible_descendants().extend_into(&mut list);
+        }
+
         list
     }
 
-    // FIXME: we can make this cheaper for CPU if we can cache
-    //        unchanged lists
-    fn flatten_internal(self: &Rc<TreeNode>, list: &mut IndexMap<KeyType, Rc<TreeNode>>) {
-        if !self.is_root() {
-            list.insert(self.key(), self.clone());
+    // Only subtrees that changed since the last call are walked again, the
+    // rest is shared from the cached rows of their roots
+    fn visible_descendants(&self) -> Rc<FlatRows> {
+        if let Some(cached) = &self.inner().flat_cache {
+            return cached.clone();
         }
 
-        let inner = self.inner();
+        let children = self.inner().children.values().cloned().collect::<Vec<_>>();
 
-        if !inner.flags.contains(TreeFlags::EXPANDED) {
-            return;
+        let mut rows = FlatRows {
+            len: children.len(),
+            segments: Vec::with_capacity(children.len()),
+        };
+        for child in children {
+            rows.segments.push(FlatSegment::Row(child.clone()));
+
+            if child.is_loading() {
+                rows.len += 1;
+                rows.segments.push(FlatSegment::Row(child.placeholder()));
+            } else if child.flags().contains(TreeFlags::EXPANDED) {
+                let nested = child.visible_descendants();
+                if nested.len > 0 {
+                    rows.len += nested.len;
+                    rows.segments.push(FlatSegment::Rows(nested));
+                }
+            }
+        }
+
+        let rows = Rc::new(rows);
+        self.inner_mut().flat_cache = Some(rows.clone());
+        rows
+    }
+
+    // Cleared whenever the subtree changes or a node in it is expanded or
+    // collapsed
+    pub(crate) fn has_flat_cache(&self) -> bool {
+        self.inner().flat_cache.is_some()
+    }
+
+    // Number of visible rows below the node, the same as the length of
+    // `flatten` without the node itself. Collapsed subtrees are skipped and
+    // cached lists are reused, so nothing is materialized
+    pub fn visible_len(&self) -> usize {
+        if self.is_loading() {
+            return 1;
+        }
+
+        if !self.flags().contains(TreeFlags::EXPANDED) {
+            return 0;
         }
 
-        for child in inner.children.values() {
-            child.flatten_internal(list)
+        let inner = self.inner();
+        if let Some(cached) = &inner.flat_cache {
+            return cached.len;
+        }
+
+        // leaves only, nothing below them can be visible
+        if inner.children_len == inner.children.len() {
+            let loading = inner
+                .children
+                .values()
+                .filter(|child| child.is_loading())
+                .count();
+            return inner.children_len + loading;
         }
+
+        inner
+            .children
+            .values()
+            .map(|child| 1 + child.visible_len())
+            .sum()
     }
 
+    // Cursors from the top level down to the node at visible `index`, empty
+    // if there is no such row
     pub(crate) fn find_by_index(self: &Rc<TreeNode>, index: usize) -> Vec<TreeCursor> {
         let mut stack = Vec::new();
-        self.find_by_index_internal(index, &mut stack);
+        if !self.find_by_index_internal(index, &mut stack) {
+            stack.clear();
+        }
+
         stack
     }
 
-    fn find_by_index_internal(self: &Rc<TreeNode>, index: usize, stack: &mut Vec<TreeCursor>) {
+    fn find_by_index_internal(&self, index: usize, stack: &mut Vec<TreeCursor>) -> bool {
+        let children = self.inner().children.values().cloned().collect::<Vec<_>>();
+
+        // visible rows before `child`
         let mut offset = 0;
-        let children = &self.inner().children;
-        for child in children.values() {
-            let child_inner = child.inner();
+        for (position, child) in children.into_iter().enumerate() {
+            let cursor = TreeCursor {
+                node: child.clone(),
+                offset: position,
+            };
 
             if offset == index {
-                stack.push(TreeCursor {
-                    offset,
-                    node: child.clone(),
-                });
-
-                return;
+                stack.push(cursor);
+                return true;
             }
 
-            // next offset
             offset += 1;
 
-            if !child_inner
-                .flags
-                .contains(TreeFlags::EXPANDED | TreeFlags::LOADING)
-            {
-                continue;
-            }
+            let visible = child.visible_len();
+            if index < offset + visible {
+                stack.push(cursor);
+
+                if child.is_loading() {
+                    stack.push(TreeCursor::placeholder(&child));
+                    return true;
+                }
 
-            if offset + child_inner.children_len >= index {
-                return self.find_by_index_internal(index - offset, stack);
+                return child.find_by_index_internal(index - offset, stack);
             }
+
+            offset += visible;
         }
 
-        panic!("Index not available")
+        false
     }
 
     // node 1
@@ -254,21 +710,26 @@ impl TreeNode {
     pub fn slice(self: &Rc<Self>, range: Range<usize>) -> TreeNodeIterator {
         TreeNodeIterator::new(self.clone(), range)
     }
+
+    pub fn connectors(self: &Rc<Self>) -> ConnectorIterator {
+        ConnectorIterator::new(self.clone())
+    }
 }
 
 // a node can be a provider (for static trees)
 impl TreeProvider for TreeNode {
     fn root(&self) -> Rc<TreeNode> {
         if self.is_root() {
-            return self.parent.upgrade().unwrap();
+            self.parent().unwrap()
         } else {
             panic!()
         }
     }
 
     fn expand(&self, node: &Rc<TreeNode>) -> TreeExpandResult {
-        let mut node = node.inner_mut();
-        node.flags.toggle(TreeFlags::EXPANDED);
+        let mut flags = node.flags();
+        flags.toggle(TreeFlags::EXPANDED);
+        node.set_flags(flags);
         TreeExpandResult::Ready
     }
 }
@@ -278,15 +739,18 @@ impl TreeItem for TreeNode {
         self.inner().data.key()
     }
 
-    fn icon(&self) -> Ref<str> {
-        Ref::map(self.inner(), |v| v.data.icon().unwrap_or(""))
+    fn icon(&self) -> Option<Ref<'_, str>> {
+        Ref::filter_map(self.inner(), |v| {
+            v.data.icon().filter(|icon| !icon.is_empty())
+        })
+        .ok()
     }
 
     fn expandable(&self) -> bool {
-        self.inner().data.flags().contains(TreeFlags::EXPANDABLE)
+        self.inner().flags.contains(TreeFlags::EXPANDABLE)
     }
 
-    fn title(&self) -> Ref<str> {
+    fn title(&self) -> Ref<'_, str> {
         Ref::map(self.inner(), |v| v.data.title())
     }
 
@@ -298,7 +762,524 @@ impl TreeItem for TreeNode {
         self.inner().data.hash()
     }
 
+    fn title_hash(&self) -> HashType {
+        self.inner().data.title_hash()
+    }
+
+    fn icon_hash(&self) -> HashType {
+        self.inner().data.icon_hash()
+    }
+
+    fn detail_hash(&self) -> HashType {
+        self.inner().data.detail_hash()
+    }
+
     fn expanded(&self) -> bool {
         self.inner().flags.contains(TreeFlags::EXPANDED)
     }
+
+    fn icon_expanded(&self) -> Option<Ref<'_, str>> {
+        Ref::filter_map(self.inner(), |v| {
+            v.data.icon_expanded().filter(|icon| !icon.is_empty())
+        })
+        .ok()
+    }
+
+    fn tooltip(&self) -> Option<Ref<'_, str>> {
+        Ref::filter_map(self.inner(), |v| v.data.tooltip()).ok()
+    }
+
+    fn is_group_header(&self) -> bool {
+        self.inner().flags.contains(TreeFlags::GROUP_HEADER)
+    }
+
+    fn load_progress(&self) -> Option<f32> {
+        self.inner().load_progress
+    }
+
+    fn check_state(&self) -> CheckState {
+        let flags = self.inner().flags;
+        if flags.contains(TreeFlags::INDETERMINATE) {
+            CheckState::Indeterminate
+        } else if flags.contains(TreeFlags::CHECKED) {
+            CheckState::Checked
+        } else {
+            CheckState::Unchecked
+        }
+    }
+
+    fn title_ranges(&self) -> Option<Vec<Range<usize>>> {
+        self.inner().title_ranges.clone()
+    }
+
+    fn height(&self) -> Option<usize> {
+        self.inner().data.height()
+    }
+
+    // Placeholders aren't among the children and have no position
+    fn set_position(&self) -> Option<(usize, usize)> {
+        let parent = self.parent()?;
+        let inner = parent.inner();
+        let index = inner.children.get_index_of(&self.key())?;
+        Some((index + 1, inner.children.len()))
+    }
+
+    fn error(&self) -> bool {
+        self.inner().flags.contains(TreeFlags::FAILED)
+    }
+
+    fn loading(&self) -> bool {
+        let inner = self.inner();
+        inner.flags.contains(TreeFlags::LOADING) || inner.data.is::<PlaceholderData>()
+    }
+
+    fn visible_children_len(&self) -> usize {
+        self.visible_len()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::plain::PlainTreeData;
+
+    pub(crate) fn expanded_root() -> Rc<TreeNode> {
+        let root = TreeNode::root();
+        root.set_flags(root.flags() | TreeFlags::EXPANDED | TreeFlags::READY);
+        root
+    }
+
+    pub(crate) fn node(parent: &Rc<TreeNode>, key: KeyType, title: &str) -> Rc<TreeNode> {
+        node_with_flags(parent, key, title, TreeFlags::empty())
+    }
+
+    pub(crate) fn node_with_flags(
+        parent: &Rc<TreeNode>,
+        key: KeyType,
+        title: &str,
+        flags: TreeFlags,
+    ) -> Rc<TreeNode> {
+        TreeNode::new(
+            parent,
+            Box::new(PlainTreeData {
+                key,
+                icon: None,
+                title: title.into(),
+                flags,
+            }),
+        )
+    }
+
+    // Data with the optional parts of `TreeData` set per test
+    #[derive(Debug)]
+    pub(crate) struct TestData {
+        pub key: KeyType,
+        pub title: &'static str,
+        pub flags: TreeFlags,
+        pub icon: Option<&'static str>,
+        pub icon_expanded: Option<&'static str>,
+        pub tooltip: Option<&'static str>,
+        pub height: Option<usize>,
+        // Stands for what a row renderer shows, only part of `detail_hash`
+        pub detail: usize,
+    }
+
+    impl TestData {
+        pub(crate) fn new(key: KeyType, title: &'static str) -> Self {
+            TestData {
+                key,
+                title,
+                flags: TreeFlags::empty(),
+                icon: None,
+                icon_expanded: None,
+                tooltip: None,
+                height: None,
+                detail: 0,
+            }
+        }
+    }
+
+    impl TreeData for TestData {
+        fn key(&self) -> KeyType {
+            self.key
+        }
+
+        fn icon(&self) -> Option<&str> {
+            self.icon
+        }
+
+        fn title(&self) -> &str {
+            self.title
+        }
+
+        fn hash(&self) -> HashType {
+            fxhash::hash64(&(
+                self.key,
+                self.title,
+                self.flags,
+                self.icon,
+                self.icon_expanded,
+                self.tooltip,
+                self.height,
+                self.detail,
+            ))
+        }
+
+        fn flags(&self) -> TreeFlags {
+            self.flags
+        }
+
+        fn title_hash(&self) -> HashType {
+            fxhash::hash64(self.title)
+        }
+
+        fn icon_hash(&self) -> HashType {
+            fxhash::hash64(&(self.icon, self.icon_expanded))
+        }
+
+        fn detail_hash(&self) -> HashType {
+            fxhash::hash64(&self.detail)
+        }
+
+        fn height(&self) -> Option<usize> {
+            self.height
+        }
+
+        fn icon_expanded(&self) -> Option<&str> {
+            self.icon_expanded
+        }
+
+        fn tooltip(&self) -> Option<&str> {
+            self.tooltip
+        }
+    }
+
+    fn titles(node: &Rc<TreeNode>) -> Vec<String> {
+        node.inner()
+            .children
+            .values()
+            .map(|c| c.title().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn insert_sorted() {
+        let root = TreeNode::root();
+        let by_title = |a: &Rc<TreeNode>, b: &Rc<TreeNode>| a.title().cmp(&*b.title());
+
+        for (key, title) in [(1, "c"), (2, "a"), (3, "d"), (4, "b"), (5, "a")] {
+            root.insert_sorted(node(&root, key, title), by_title);
+        }
+
+        assert_eq!(titles(&root), ["a", "a", "b", "c", "d"]);
+        assert_eq!(root.children_len(), 5);
+    }
+
+    #[test]
+    fn insert_at() {
+        let root = TreeNode::root();
+        root.insert(vec![node(&root, 1, "b"), node(&root, 2, "c")]);
+
+        root.insert_at(0, vec![node(&root, 3, "a")]);
+        assert_eq!(titles(&root), ["a", "b", "c"]);
+
+        root.insert_at(3, vec![node(&root, 4, "d")]);
+        assert_eq!(titles(&root), ["a", "b", "c", "d"]);
+
+        root.insert_at(2, vec![node(&root, 5, "b1"), node(&root, 6, "b2")]);
+        root.insert_at(100, vec![node(&root, 7, "e")]);
+        assert_eq!(titles(&root), ["a", "b", "b1", "b2", "c", "d", "e"]);
+        assert_eq!(root.children_len(), 7);
+    }
+
+    #[test]
+    fn insert_pre_expanded_subtree() {
+        let root = expanded_root();
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+
+        let subtree = node_with_flags(&root, 1, "1", folder).build(|parent| {
+            vec![
+                node_with_flags(parent, 11, "1.1", folder)
+                    .build(|parent| vec![node(parent, 111, "1.1.1")]),
+                node(parent, 12, "1.2"),
+            ]
+        });
+
+        root.insert(vec![subtree.clone(), node(&root, 2, "2")]);
+
+        assert_eq!(root.children_len(), 5);
+        assert!(subtree.flags().contains(TreeFlags::READY));
+
+        let flat = root.flatten();
+        assert_eq!(
+            flat.keys().copied().collect::<Vec<_>>(),
+            [1, 11, 111, 12, 2]
+        );
+    }
+
+    #[test]
+    fn visible_len_matches_flatten() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", TreeFlags::EXPANDABLE)
+                            .build(|parent| vec![node(parent, 111, "1.1.1")]),
+                        node_with_flags(parent, 12, "1.2", folder)
+                            .build(|parent| vec![node(parent, 121, "1.2.1")]),
+                        node_with_flags(parent, 13, "1.3", TreeFlags::EXPANDABLE),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let check = |root: &Rc<TreeNode>| {
+            assert_eq!(root.visible_len(), root.flatten().len());
+            root.walk(&mut |node| assert_eq!(1 + node.visible_len(), node.flatten().len()));
+        };
+
+        // cold caches first, then the ones `flatten` filled
+        assert_eq!(root.visible_len(), 6);
+        check(&root);
+
+        let loading = root.find(13).unwrap();
+        loading.set_flags(loading.flags() | TreeFlags::LOADING);
+        check(&root);
+
+        let collapsed = root.find(11).unwrap();
+        collapsed.set_flags(collapsed.flags() | TreeFlags::EXPANDED);
+        assert_eq!(root.visible_len(), 8);
+        check(&root);
+
+        root.find(1)
+            .unwrap()
+            .set_flags(folder - TreeFlags::EXPANDED);
+        assert_eq!(root.visible_len(), 2);
+        check(&root);
+    }
+
+    #[test]
+    fn depth_saturates() {
+        let mut parent = TreeNode::root();
+        for key in 0..MAX_DEPTH as usize + 10 {
+            parent = node(&parent, key, "deep");
+        }
+
+        assert_eq!(parent.depth(), MAX_DEPTH);
+    }
+
+    #[test]
+    fn find_by_index() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", folder).build(|parent| {
+                            vec![node_with_flags(parent, 111, "1.1.1", folder)
+                                .build(|parent| vec![node(parent, 1111, "1.1.1.1")])]
+                        }),
+                        node_with_flags(parent, 12, "1.2", TreeFlags::EXPANDABLE)
+                            .build(|parent| vec![node(parent, 121, "1.2.1")]),
+                        node(parent, 13, "1.3"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let stack = |index| {
+            root.find_by_index(index)
+                .iter()
+                .map(|cursor| (cursor.node.key(), cursor.offset))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(stack(0), [(1, 0)]);
+        assert_eq!(stack(3), [(1, 0), (11, 0), (111, 0), (1111, 0)]);
+        assert_eq!(stack(4), [(1, 0), (12, 1)]);
+        assert_eq!(stack(5), [(1, 0), (13, 2)]);
+        assert_eq!(stack(6), [(2, 1)]);
+        assert_eq!(stack(7), []);
+
+        let keys = root.slice(2..6).map(|node| node.key()).collect::<Vec<_>>();
+        assert_eq!(keys, [111, 1111, 12, 13]);
+    }
+
+    #[test]
+    fn loading_placeholder() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", TreeFlags::EXPANDABLE),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let loading = root.get(1).unwrap().get(11).unwrap();
+        loading.set_flags(loading.flags() | TreeFlags::LOADING);
+
+        let placeholder = PlaceholderData::key_for(11);
+        assert_eq!(keys(&root), [1, 11, placeholder, 12, 2]);
+        assert_eq!(root.visible_children_len(), 5);
+
+        let stack = root.find_by_index(2);
+        assert_eq!(stack.last().unwrap().node.key(), placeholder);
+        assert_eq!(stack.len(), 3);
+
+        let slice = root.slice(1..5).map(|node| node.key()).collect::<Vec<_>>();
+        assert_eq!(slice, [11, placeholder, 12, 2]);
+
+        // the children arrive
+        loading.insert(vec![node(&loading, 111, "1.1.1")]);
+        loading.set_flags((loading.flags() - TreeFlags::LOADING) | TreeFlags::EXPANDED);
+        assert_eq!(keys(&root), [1, 11, 111, 12, 2]);
+    }
+
+    #[test]
+    fn remove_child() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", folder).build(|parent| {
+                            vec![node(parent, 111, "1.1.1"), node(parent, 112, "1.1.2")]
+                        }),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        assert_eq!(root.children_len(), 6);
+
+        let folder = root.get(1).unwrap();
+        let removed = folder.remove_child(11).unwrap();
+        assert_eq!(removed.children_len(), 2);
+        assert_eq!(folder.children_len(), 1);
+        assert_eq!(root.children_len(), 3);
+        assert_eq!(keys(&root), [1, 12, 2]);
+
+        assert!(folder.get(12).unwrap().remove_self());
+        assert!(folder.remove_child(12).is_none());
+        assert_eq!(root.children_len(), 2);
+
+        assert!(!root.remove_self());
+    }
+
+    #[test]
+    fn move_updates_depth() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| vec![node(parent, 11, "1.1")]),
+                node_with_flags(root, 2, "2", folder).build(|parent| {
+                    vec![node_with_flags(parent, 21, "2.1", folder)
+                        .build(|parent| vec![node_with_flags(parent, 211, "2.1.1", folder)])]
+                }),
+            ]
+        });
+
+        let target = root.find(211).unwrap();
+        root.get(1).unwrap().move_to(&target, 0);
+
+        let depths = root
+            .flatten()
+            .values()
+            .map(|node| (node.key(), node.depth()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(depths, [(2, 1), (21, 2), (211, 3), (1, 4), (11, 5)]);
+    }
+
+    #[test]
+    fn ancestors() {
+        let root = expanded_root().build(|root| {
+            vec![node(root, 1, "1").build(|parent| {
+                vec![node(parent, 11, "1.1").build(|parent| vec![node(parent, 111, "1.1.1")])]
+            })]
+        });
+
+        let leaf = root.find(111).unwrap();
+        let ancestors = leaf
+            .ancestors()
+            .iter()
+            .map(|node| node.key())
+            .collect::<Vec<_>>();
+        assert_eq!(ancestors, [11, 1]);
+        assert_eq!(leaf.path_keys(), [1, 11, 111]);
+
+        assert!(root.get(1).unwrap().ancestors().is_empty());
+        assert!(root.path_keys().is_empty());
+    }
+
+    fn keys(root: &Rc<TreeNode>) -> Vec<KeyType> {
+        root.flatten().keys().copied().collect()
+    }
+
+    #[test]
+    fn flatten_cache_invalidation() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![node_with_flags(parent, 11, "1.1", folder)
+                        .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        assert_eq!(keys(&root), [1, 11, 111, 2]);
+
+        let nested = root.get(1).unwrap().get(11).unwrap();
+        nested.set_flags(nested.flags() - TreeFlags::EXPANDED);
+        assert_eq!(keys(&root), [1, 11, 2]);
+
+        nested.insert(vec![node(&nested, 112, "1.1.2")]);
+        nested.set_flags(nested.flags() | TreeFlags::EXPANDED);
+        assert_eq!(keys(&root), [1, 11, 111, 112, 2]);
+
+        nested.clear_children();
+        assert_eq!(keys(&root), [1, 11, 2]);
+    }
+
+    #[test]
+    fn flatten_shares_untouched_subtrees() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            (1..=3)
+                .map(|key| {
+                    node_with_flags(root, key, "folder", folder)
+                        .build(|parent| vec![node(parent, key * 10, "file")])
+                })
+                .collect()
+        });
+
+        assert_eq!(keys(&root), [1, 10, 2, 20, 3, 30]);
+        let cached = |key| root.get(key).unwrap().visible_descendants();
+        let (first, last) = (cached(1), cached(3));
+
+        let folder = root.get(2).unwrap();
+        folder.set_flags(folder.flags() - TreeFlags::EXPANDED);
+        assert_eq!(keys(&root), [1, 10, 2, 3, 30]);
+
+        // only the root list is built again, around the same rows
+        assert!(Rc::ptr_eq(&first, &cached(1)));
+        assert!(Rc::ptr_eq(&last, &cached(3)));
+        assert!(root
+            .visible_descendants()
+            .segments
+            .iter()
+            .any(|segment| matches!(segment, FlatSegment::Rows(rows) if Rc::ptr_eq(rows, &first))));
+    }
 }
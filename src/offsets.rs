@@ -0,0 +1,160 @@
+// Vertical offsets of rows with different heights, kept in a Fenwick tree so
+// both the offset of a row and the row at an offset are found in O(log n)
+// and a single row can change its height without rebuilding the rest
+#[derive(Clone, Debug, Default)]
+pub struct RowOffsets {
+    heights: Vec<usize>,
+    // `tree[k - 1]` holds the sum of the `k & -k` heights ending at row `k - 1`
+    tree: Vec<usize>,
+}
+
+impl RowOffsets {
+    pub fn new(heights: impl IntoIterator<Item = usize>) -> Self {
+        let heights = heights.into_iter().collect::<Vec<_>>();
+        let mut tree = heights.clone();
+
+        let len = tree.len();
+        for k in 1..=len {
+            let parent = k + lowbit(k);
+            if parent <= len {
+                tree[parent - 1] += tree[k - 1];
+            }
+        }
+
+        RowOffsets { heights, tree }
+    }
+
+    pub fn uniform(count: usize, height: usize) -> Self {
+        Self::new(std::iter::repeat_n(height, count))
+    }
+
+    pub fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heights.is_empty()
+    }
+
+    pub fn height(&self, index: usize) -> usize {
+        self.heights.get(index).copied().unwrap_or(0)
+    }
+
+    // Returns `true` if the height changed
+    pub fn set_height(&mut self, index: usize, height: usize) -> bool {
+        let Some(old) = self.heights.get_mut(index) else {
+            return false;
+        };
+
+        if *old == height {
+            return false;
+        }
+
+        // modular arithmetic handles shrinking rows as well
+        let delta = height.wrapping_sub(std::mem::replace(old, height));
+        let mut k = index + 1;
+        while k <= self.tree.len() {
+            self.tree[k - 1] = self.tree[k - 1].wrapping_add(delta);
+            k += lowbit(k);
+        }
+
+        true
+    }
+
+    // Top of the row at `index`, indexes past the end give the total height
+    pub fn offset(&self, index: usize) -> usize {
+        let mut k = index.min(self.len());
+        let mut sum = 0;
+        while k > 0 {
+            sum += self.tree[k - 1];
+            k -= lowbit(k);
+        }
+
+        sum
+    }
+
+    pub fn total(&self) -> usize {
+        self.offset(self.len())
+    }
+
+    // Row that covers `y`, offsets below the last row give the last row
+    pub fn index_at(&self, y: usize) -> usize {
+        let len = self.len();
+        if len == 0 {
+            return 0;
+        }
+
+        let mut pos = 0;
+        let mut rest = y;
+        let mut step = 1 << len.ilog2();
+        while step > 0 {
+            if pos + step <= len && self.tree[pos + step - 1] <= rest {
+                pos += step;
+                rest -= self.tree[pos - 1];
+            }
+
+            step >>= 1;
+        }
+
+        pos.min(len - 1)
+    }
+}
+
+fn lowbit(k: usize) -> usize {
+    k & k.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_rows() {
+        let offsets = RowOffsets::uniform(100, 24);
+        assert_eq!(offsets.total(), 2400);
+        assert_eq!(offsets.offset(10), 240);
+        assert_eq!(offsets.index_at(0), 0);
+        assert_eq!(offsets.index_at(239), 9);
+        assert_eq!(offsets.index_at(240), 10);
+        assert_eq!(offsets.index_at(10_000), 99);
+    }
+
+    #[test]
+    fn mixed_heights() {
+        let heights = [24, 48, 24, 72, 24, 24, 96];
+        let offsets = RowOffsets::new(heights);
+
+        let mut top = 0;
+        for (index, height) in heights.iter().enumerate() {
+            assert_eq!(offsets.offset(index), top);
+            assert_eq!(offsets.index_at(top), index);
+            assert_eq!(offsets.index_at(top + height - 1), index);
+            top += height;
+        }
+
+        assert_eq!(offsets.total(), top);
+        assert_eq!(offsets.index_at(100), 3);
+    }
+
+    #[test]
+    fn set_height() {
+        let mut offsets = RowOffsets::uniform(10, 24);
+        assert!(offsets.set_height(3, 100));
+        assert!(!offsets.set_height(3, 100));
+        assert_eq!(offsets.offset(4), 3 * 24 + 100);
+        assert_eq!(offsets.index_at(3 * 24 + 99), 3);
+
+        assert!(offsets.set_height(3, 10));
+        assert_eq!(offsets.total(), 9 * 24 + 10);
+        assert_eq!(offsets.index_at(3 * 24 + 10), 4);
+        assert!(!offsets.set_height(10, 1));
+    }
+
+    #[test]
+    fn empty() {
+        let offsets = RowOffsets::default();
+        assert!(offsets.is_empty());
+        assert_eq!(offsets.total(), 0);
+        assert_eq!(offsets.index_at(100), 0);
+    }
+}
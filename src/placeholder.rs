@@ -0,0 +1,51 @@
+use crate::node::TreeFlags;
+
+use super::node::TreeData;
+use super::{HashType, KeyType, ROOT_KEY};
+
+// Spinner row shown under a node while its children are loading
+#[derive(Debug)]
+pub struct PlaceholderData {
+    pub key: KeyType,
+}
+
+const PLACEHOLDER_BIT: KeyType = 1 << (KeyType::BITS - 1);
+
+impl PlaceholderData {
+    // The key of the loading node with the top bit set
+    pub fn key_for(key: KeyType) -> KeyType {
+        key | PLACEHOLDER_BIT
+    }
+
+    pub fn is_key(key: KeyType) -> bool {
+        key & PLACEHOLDER_BIT != 0 && key != ROOT_KEY
+    }
+
+    // Regular nodes can't use the top half of the keys, and neither the
+    // key right below it, whose placeholder would get the root key
+    pub fn is_reserved(key: KeyType) -> bool {
+        key >= ROOT_KEY >> 1
+    }
+}
+
+impl TreeData for PlaceholderData {
+    fn key(&self) -> KeyType {
+        self.key
+    }
+
+    fn icon(&self) -> Option<&str> {
+        None
+    }
+
+    fn title(&self) -> &str {
+        "Loading…"
+    }
+
+    fn hash(&self) -> HashType {
+        fxhash::hash64(&self.key)
+    }
+
+    fn flags(&self) -> TreeFlags {
+        TreeFlags::empty()
+    }
+}
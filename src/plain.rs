@@ -5,7 +5,7 @@ use crate::node::TreeFlags;
 use super::node::TreeData;
 use super::{HashType, KeyType};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PlainTreeData {
     pub key: KeyType,
     pub icon: Option<Cow<'static, str>>,
@@ -30,19 +30,33 @@ impl TreeData for PlainTreeData {
         self.flags
     }
 
+    // Covers every field the row renders, the per-field hashes below let
+    // the renderer skip the parts that didn't change
     fn hash(&self) -> HashType {
-        fxhash::hash64(&(&self.key, &self.title))
+        fxhash::hash64(&(&self.key, &self.title, &self.icon, &self.flags))
+    }
+
+    fn title_hash(&self) -> HashType {
+        fxhash::hash64(&self.title)
+    }
+
+    fn icon_hash(&self) -> HashType {
+        fxhash::hash64(&self.icon)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+
     pub use super::*;
+    use crate::item::{ItemHashes, TreeItem};
+    use crate::node::tests::{expanded_root, TestData};
     use crate::node::TreeNode;
 
     #[test]
     fn test() {
-        let tree = TreeNode::root().build(|parent| {
+        let tree = expanded_root().build(|parent| {
             vec![
                 TreeNode::new(
                     parent,
@@ -89,14 +103,133 @@ mod tests {
 
         assert_eq!(tree.children_len(), 4);
 
-        let stack = tree.flatten();
+        // the folder is collapsed, its children aren't visible
+        let titles = |tree: &Rc<TreeNode>| {
+            tree.slice(0..100)
+                .map(|node| node.title().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(&tree), ["1", "2"]);
+        assert_eq!(tree.flatten().len(), 2);
+
+        let folder = tree.get(1).unwrap();
+        folder.set_flags(folder.flags() | TreeFlags::EXPANDED);
+        assert_eq!(titles(&tree), ["1", "1.1", "1.2", "2"]);
+    }
+
+    #[test]
+    fn icon_only_change() {
+        let root = TreeNode::root();
+        let mut data = PlainTreeData {
+            key: 1,
+            icon: Some("folder".into()),
+            title: "1".into(),
+            flags: TreeFlags::empty(),
+        };
+
+        let before = ItemHashes::of(&*TreeNode::new(&root, Box::new(data.clone())));
+        data.icon = Some("folder-open".into());
+        let after = ItemHashes::of(&*TreeNode::new(&root, Box::new(data)));
+
+        assert_ne!(before, after);
+        assert_ne!(before.hash, after.hash);
+        assert_ne!(before.icon, after.icon);
+        assert_eq!(before.title, after.title);
+    }
+
+    #[test]
+    fn hash_covers_rendered_fields() {
+        let data = PlainTreeData {
+            key: 1,
+            icon: None,
+            title: "1".into(),
+            flags: TreeFlags::empty(),
+        };
+
+        let mut icon = data.clone();
+        icon.icon = Some("file".into());
+        let mut flags = data.clone();
+        flags.flags = TreeFlags::EXPANDABLE;
+
+        assert_ne!(data.hash(), icon.hash());
+        assert_ne!(data.hash(), flags.hash());
+        assert_eq!(data.hash(), data.clone().hash());
+
+        // a node with the new icon refreshes its row
+        let tree = crate::dynamic::DynamicTree::new(
+            expanded_root().build(|root| vec![TreeNode::new(root, Box::new(data))]),
+            Default::default(),
+        );
+        tree.set_sync_updates(true);
+        assert!(tree.set_data_if_changed(1, Box::new(icon)));
+        assert_eq!(tree.find(1).unwrap().icon().as_deref(), Some("file"));
+    }
+
+    #[test]
+    fn icon_and_flags_through_node() {
+        let root = TreeNode::root();
+        let folder = TreeNode::new(
+            &root,
+            Box::new(PlainTreeData {
+                key: 1,
+                icon: Some("folder".into()),
+                title: "src".into(),
+                flags: TreeFlags::EXPANDABLE,
+            }),
+        );
+
+        assert_eq!(folder.icon().as_deref(), Some("folder"));
+        assert!(folder.expandable());
+        assert!(folder.flags().contains(TreeFlags::EXPANDABLE));
+
+        let file = TreeNode::new(
+            &root,
+            Box::new(PlainTreeData {
+                key: 2,
+                icon: None,
+                title: "main.rs".into(),
+                flags: TreeFlags::empty(),
+            }),
+        );
+
+        assert!(file.icon().is_none());
+        assert!(!file.expandable());
+    }
+
+    #[test]
+    fn tooltip_through_node() {
+        let root = TreeNode::root();
+        let data = PlainTreeData {
+            key: 1,
+            icon: None,
+            title: "a very long title".into(),
+            flags: TreeFlags::empty(),
+        };
+
+        let plain = TreeNode::new(&root, Box::new(data));
+        assert_eq!(plain.tooltip().as_deref(), Some("a very long title"));
+
+        let untitled = TreeNode::new(&root, Box::new(TestData::new(2, "a very long title")));
+        assert!(untitled.tooltip().is_none());
+    }
+
+    #[test]
+    fn typed_data() {
+        let root = TreeNode::root();
+        let node = TreeNode::new(
+            &root,
+            Box::new(PlainTreeData {
+                key: 1,
+                icon: Some("file".into()),
+                title: "main.rs".into(),
+                flags: TreeFlags::empty(),
+            }),
+        );
 
-        println!("Tree {:#?}", stack.len());
+        let data = node.data_as::<PlainTreeData>().unwrap();
+        assert_eq!(data.title, "main.rs");
+        assert_eq!(data.icon.as_deref(), Some("file"));
 
-        // let mut item = tree.slice(0..100);
-        // assert_eq!(*item.next().unwrap().name(), "1");
-        // assert_eq!(*item.next().unwrap().name(), "1.1");
-        // assert_eq!(*item.next().unwrap().name(), "1.2");
-        // assert_eq!(*item.next().unwrap().name(), "2");
+        assert!(root.data_as::<PlainTreeData>().is_none());
     }
 }
@@ -1,13 +1,88 @@
 use std::rc::Rc;
 
-use super::node::TreeNode;
+use futures::channel::oneshot::Receiver;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use wasm_bindgen::JsValue;
+
+use super::node::{TreeData, TreeNode};
 
 pub enum TreeExpandResult {
     Ready,
-    Async(futures::channel::oneshot::Receiver<Vec<Rc<TreeNode>>>),
+    // A failed load leaves the node collapsed so it can be expanded again
+    Async(futures::channel::oneshot::Receiver<Result<Vec<Rc<TreeNode>>, JsValue>>),
+    // Awaited by the tree itself, see `AsyncTreeProvider`
+    Future(LocalBoxFuture<'static, Result<Vec<Rc<TreeNode>>, ProviderError>>),
+}
+
+// Errors reach `on_expand_error` as they are, whatever the provider kind
+pub type ExpandJob = LocalBoxFuture<'static, Result<Vec<Rc<TreeNode>>, ProviderError>>;
+
+impl TreeExpandResult {
+    // The load the tree awaits, `None` if the children are already there
+    pub fn into_job(self) -> Option<ExpandJob> {
+        match self {
+            TreeExpandResult::Ready => None,
+            TreeExpandResult::Async(receiver) => Some(
+                async move {
+                    match receiver.await {
+                        Ok(result) => result.map_err(ProviderError::Js),
+                        Err(_) => Err(ProviderError::Message("Expand job was cancelled".into())),
+                    }
+                }
+                .boxed_local(),
+            ),
+            TreeExpandResult::Future(future) => Some(future),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ProviderError {
+    Js(JsValue),
+    Message(String),
+}
+
+impl From<JsValue> for ProviderError {
+    fn from(value: JsValue) -> Self {
+        ProviderError::Js(value)
+    }
 }
 
 pub trait TreeProvider {
     fn root(&self) -> Rc<TreeNode>;
     fn expand(&self, node: &Rc<TreeNode>) -> TreeExpandResult;
+
+    // Called once a node flagged `UNRESOLVED` becomes visible, the received
+    // data replaces the placeholder one
+    fn resolve_display(&self, _node: &Rc<TreeNode>) -> Option<Receiver<Box<dyn TreeData>>> {
+        None
+    }
+}
+
+// For providers that load children over the network and would rather
+// await than send them over a channel:
+//
+//     fn expand(&self, node: &Rc<TreeNode>) -> LocalBoxFuture<...> {
+//         let path = node.data_as::<File>().unwrap().path.clone();
+//         async move { fetch_children(path).await }.boxed_local()
+//     }
+//
+// Every `AsyncTreeProvider` is a `TreeProvider` as well
+pub trait AsyncTreeProvider {
+    fn root(&self) -> Rc<TreeNode>;
+    fn expand(
+        &self,
+        node: &Rc<TreeNode>,
+    ) -> LocalBoxFuture<'static, Result<Vec<Rc<TreeNode>>, ProviderError>>;
+}
+
+impl<P: AsyncTreeProvider> TreeProvider for P {
+    fn root(&self) -> Rc<TreeNode> {
+        AsyncTreeProvider::root(self)
+    }
+
+    fn expand(&self, node: &Rc<TreeNode>) -> TreeExpandResult {
+        TreeExpandResult::Future(AsyncTreeProvider::expand(self, node))
+    }
 }
@@ -0,0 +1,145 @@
+use std::rc::Rc;
+
+use super::item::TreeItem;
+use super::node::{TreeFlags, TreeNode};
+use super::plain::PlainTreeData;
+use super::KeyType;
+
+// Plain description of a subtree, e.g. from a config file or an API
+// response. Nodes with children become loaded folders
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeSpec {
+    pub key: KeyType,
+    pub title: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub icon: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub children: Vec<TreeSpec>,
+    // Ignored for nodes without children
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub expanded: bool,
+}
+
+impl TreeSpec {
+    // Describes an existing subtree, the data of every node is read through
+    // `TreeItem`
+    pub fn of(node: &Rc<TreeNode>) -> Self {
+        let icon = node.icon().map(|icon| icon.to_string());
+        let children = node.inner().children.values().map(TreeSpec::of).collect();
+
+        TreeSpec {
+            key: node.key(),
+            title: node.title().to_string(),
+            icon,
+            children,
+            expanded: node.expanded(),
+        }
+    }
+}
+
+impl TreeNode {
+    pub fn from_spec(parent: &Rc<TreeNode>, spec: TreeSpec) -> Rc<TreeNode> {
+        let mut flags = TreeFlags::empty();
+        if !spec.children.is_empty() {
+            flags.insert(TreeFlags::EXPANDABLE | TreeFlags::READY);
+            flags.set(TreeFlags::EXPANDED, spec.expanded);
+        }
+
+        let data = PlainTreeData {
+            key: spec.key,
+            icon: spec.icon.map(Into::into),
+            title: spec.title,
+            flags,
+        };
+
+        let children = spec.children;
+        TreeNode::new(parent, Box::new(data)).build(|node| {
+            children
+                .into_iter()
+                .map(|child| TreeNode::from_spec(node, child))
+                .collect()
+        })
+    }
+
+    // An expanded root with the given top level nodes
+    pub fn root_from_specs(specs: Vec<TreeSpec>) -> Rc<TreeNode> {
+        let root = TreeNode::root();
+        root.set_flags(root.flags() | TreeFlags::EXPANDED | TreeFlags::READY);
+        root.build(|root| {
+            specs
+                .into_iter()
+                .map(|spec| TreeNode::from_spec(root, spec))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(key: KeyType, title: &str) -> TreeSpec {
+        TreeSpec {
+            key,
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    fn three_levels() -> Vec<TreeSpec> {
+        vec![
+            TreeSpec {
+                key: 1,
+                title: "src".into(),
+                icon: Some("folder".into()),
+                expanded: true,
+                children: vec![
+                    TreeSpec {
+                        key: 11,
+                        title: "view".into(),
+                        children: vec![leaf(111, "mod.rs"), leaf(112, "row.rs")],
+                        ..Default::default()
+                    },
+                    leaf(12, "lib.rs"),
+                ],
+            },
+            leaf(2, "Cargo.toml"),
+        ]
+    }
+
+    #[test]
+    fn builds_nested_nodes() {
+        let root = TreeNode::root_from_specs(three_levels());
+        assert_eq!(root.children_len(), 6);
+
+        let depths = root
+            .slice(0..10)
+            .map(|node| (node.key(), node.depth()))
+            .collect::<Vec<_>>();
+        assert_eq!(depths, [(1, 1), (11, 2), (12, 2), (2, 1)]);
+
+        let folder = root.find(11).unwrap();
+        assert_eq!(folder.children_len(), 2);
+        assert!(folder
+            .flags()
+            .contains(TreeFlags::EXPANDABLE | TreeFlags::READY));
+        assert!(!folder.expanded());
+        assert_eq!(root.find(111).unwrap().depth(), 3);
+        assert!(!root.find(2).unwrap().expandable());
+    }
+
+    #[test]
+    fn round_trip() {
+        let specs = three_levels();
+        let root = TreeNode::root_from_specs(specs.clone());
+        let described = root
+            .inner()
+            .children
+            .values()
+            .map(TreeSpec::of)
+            .collect::<Vec<_>>();
+
+        assert_eq!(described, specs);
+    }
+}
@@ -1,375 +1,4295 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap};
-use std::rc::Rc;
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::{Rc, Weak};
 
 use js_sys::{Function, Reflect};
+use skima::web::Callback;
 use wasm_bindgen::prelude::{wasm_bindgen, Closure};
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{Element, HtmlElement, Node, Text};
+use web_sys::{Element, HtmlElement, HtmlInputElement, Node, Text};
 
-use super::dynamic::TreeSubscriber;
-use super::item::TreeItem;
-use super::KeyType;
+use super::dynamic::{TreeChange, TreeSubscriber};
+use super::item::{displayed_icon, CheckState, ItemHashes, TreeItem};
+use super::node::TreeNode;
+use super::offsets::RowOffsets;
+use super::{HashType, KeyType};
 
-pub trait TreeController {
-    fn item(&self, index: usize) -> Rc<dyn TreeItem>;
-    fn count(&self) -> usize;
-    fn handle_click(&self, key: usize);
-    fn add_subscriber(&self, subscriber: Rc<dyn TreeSubscriber>);
+#[derive(Clone, Debug, PartialEq)]
+pub enum TreeError {
+    NoDocument,
+    // A DOM call threw
+    Dom(JsValue),
+    // A `data-key` attribute that doesn't hold a key
+    InvalidKey(String),
 }
 
-pub struct TreeState {
-    count: usize,
-    rendered: HashMap<KeyType, RenderedItem>,
-    pool: Vec<RenderedItem>,
-    offset: usize,
-    size: (usize, usize),
+impl From<JsValue> for TreeError {
+    fn from(value: JsValue) -> Self {
+        TreeError::Dom(value)
+    }
 }
 
-pub struct TreeView {
-    state: RefCell<TreeState>,
+pub fn parse_key(value: &str) -> Result<KeyType, TreeError> {
+    value
+        .parse::<KeyType>()
+        .map_err(|_| TreeError::InvalidKey(value.to_string()))
+}
 
-    pub container: HtmlElement,
-    pub scroll: HtmlElement,
-    item_height: usize,
+fn document() -> Result<web_sys::Document, TreeError> {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or(TreeError::NoDocument)
+}
 
-    ctrl: Rc<dyn TreeController>,
+// Logs DOM failures outside of a paint, the next update rewrites the rows
+fn report<E: Into<TreeError>>(result: Result<(), E>) {
+    if let Err(err) = result {
+        tracing::error!("Tree DOM update failed: {:?}", err.into());
+    }
+}
 
-    #[allow(unused)]
-    on_resize: Closure<dyn Fn(JsValue)>,
-    #[allow(unused)]
-    on_click: Closure<dyn Fn(JsValue)>,
-    #[allow(unused)]
-    on_scroll: Closure<dyn Fn(JsValue)>,
-    #[allow(unused)]
-    observer: JsValue,
+fn event_target(ev: &web_sys::Event) -> Option<Element> {
+    ev.target()?.dyn_into::<Element>().ok()
 }
 
-impl Drop for TreeView {
-    fn drop(&mut self) {
-        self.container.remove();
-        unobserve(&self.observer)
+// Whether the event happened inside an element matching `selector`
+fn within(target: &Element, selector: &str) -> bool {
+    matches!(target.closest(selector), Ok(Some(_)))
+}
+
+// Key of the row an event happened in
+fn event_key(ev: &web_sys::Event) -> Option<KeyType> {
+    let row = event_target(ev)?.closest("[data-key]").ok()??;
+    row_key(&row)
+}
+
+// The attribute is readable by any script on the page, so a value that
+// doesn't parse ignores the event
+fn row_key(row: &Element) -> Option<KeyType> {
+    let value = row.get_attribute("data-key")?;
+
+    match parse_key(&value) {
+        Ok(key) => Some(key),
+        Err(err) => {
+            tracing::warn!("Ignoring event on a row: {:?}", err);
+            None
+        }
     }
 }
 
-impl TreeView {
-    pub fn render(handle: Rc<dyn TreeController>) -> Rc<TreeView> {
-        let tree = Rc::<TreeView>::new_cyclic(|this| {
-            let document = web_sys::window().unwrap().document().unwrap();
-            let container: HtmlElement = document.create_element("div").unwrap().unchecked_into();
-            container.set_attribute("class", "tree").unwrap();
+// Modifier keys held during a click on a row
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClickModifiers {
+    // Ctrl or Cmd, toggles a single row
+    pub toggle: bool,
+    // Shift, extends the selection from the anchor row
+    pub range: bool,
+}
 
-            let scroll: HtmlElement = document.create_element("div").unwrap().unchecked_into();
-            scroll.set_attribute("class", "tree-scroll").unwrap();
+// Where a dragged row lands relative to the row under the cursor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPosition {
+    Before,
+    After,
+    Inside,
+}
 
-            container.append_child(&scroll).unwrap();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DropTarget {
+    pub key: KeyType,
+    pub position: DropPosition,
+}
 
-            let on_click: Closure<dyn Fn(JsValue)> = Closure::new({
-                let this = this.clone();
-                move |ev: JsValue| {
-                    this.upgrade().unwrap().handle_click(ev.unchecked_into());
-                }
-            });
+// Folders take the middle half of the row as `Inside`, other rows are split
+// in two. `y` is measured from the top of the row
+pub fn drop_position(y: f64, height: f64, folder: bool) -> DropPosition {
+    let ratio = if height > 0.0 { y / height } else { 0.0 };
+    if folder {
+        if ratio < 0.25 {
+            DropPosition::Before
+        } else if ratio < 0.75 {
+            DropPosition::Inside
+        } else {
+            DropPosition::After
+        }
+    } else if ratio < 0.5 {
+        DropPosition::Before
+    } else {
+        DropPosition::After
+    }
+}
 
-            container
-                .add_event_listener_with_callback("click", on_click.as_ref().unchecked_ref())
-                .unwrap();
+// Custom content of the rows next to the arrow, icon and title, e.g. badges
+// or trailing buttons. Rows are pooled, the container of one item is reused
+// for another: `update` has to bring everything `render` added in line with
+// the new item, and `recycle` runs before a row goes to the pool so state
+// that only made sense for the old item can be dropped. What the renderer
+// shows is expected to be covered by `TreeItem::detail_hash`, `update` is
+// skipped while it and the key stay the same
+pub trait RowRenderer: std::fmt::Debug {
+    fn render(&self, container: &Element, item: &dyn TreeItem);
+    fn update(&self, container: &Element, item: &dyn TreeItem);
+    fn recycle(&self, _container: &Element) {}
+}
 
-            let on_scroll: Closure<dyn Fn(JsValue)> = Closure::new({
-                let this = this.clone();
-                move |_: JsValue| {
-                    let this = this.upgrade().unwrap();
-                    let offset = this.container.scroll_top();
-                    this.update_scroll(offset as usize);
-                }
-            });
+pub trait TreeController {
+    fn item(&self, index: usize) -> Rc<dyn TreeItem>;
+    fn count(&self) -> usize;
+    fn handle_click(&self, key: usize, modifiers: ClickModifiers);
+    fn handle_dbl_click(&self, _key: KeyType) {}
+    // `x` and `y` are the client coordinates of the right click
+    fn handle_context_menu(&self, _key: KeyType, _x: i32, _y: i32) {}
+    // Called when an inline edit started with `TreeView::begin_edit` is
+    // committed
+    fn handle_rename(&self, _key: KeyType, _title: String) {}
+    fn add_subscriber(&self, subscriber: Rc<dyn TreeSubscriber>);
+    fn remove_subscriber(&self, subscriber: &Rc<dyn TreeSubscriber>);
 
-            container
-                .add_event_listener_with_callback("scroll", on_scroll.as_ref().unchecked_ref())
-                .unwrap();
+    // Height of the row at `index` if it differs from the view's
+    // `item_height`, asked for every row when the list changes so it
+    // shouldn't resolve anything lazily
+    fn row_height(&self, _index: usize) -> Option<usize> {
+        None
+    }
 
-            let on_resize: Closure<dyn Fn(JsValue)> = Closure::new({
-                let this = this.clone();
-                move |size: JsValue| {
-                    let width = Reflect::get_u32(&size, 0).unwrap().as_f64().unwrap() as usize;
-                    let height = Reflect::get_u32(&size, 1).unwrap().as_f64().unwrap() as usize;
-                    this.upgrade().unwrap().update_size(width, height);
-                }
-            });
+    // Index of the group header that should be pinned while the row at
+    // `index` is the first visible one
+    fn sticky_header(&self, _index: usize) -> Option<usize> {
+        None
+    }
 
-            let observer = observe(&container, on_resize.as_ref().unchecked_ref());
-            let size = (0, 0);
-            let offset = 0;
-            let item_height = 24;
-            let count = handle.count();
+    // Returns `true` if there was an active filter to clear
+    fn clear_filter(&self) -> bool {
+        false
+    }
 
-            let tree = TreeView {
-                ctrl: handle,
-                state: RefCell::new(TreeState {
-                    count,
-                    size,
-                    offset,
-                    rendered: Default::default(),
-                    pool: vec![],
-                }),
-                scroll,
-                container,
-                observer,
-                on_resize,
-                on_scroll,
-                on_click,
-                item_height,
-            };
+    // Returns `true` if the node was expanded and got collapsed
+    fn collapse(&self, _key: KeyType) -> bool {
+        false
+    }
 
-            tree.update();
+    // Returns `true` if the node was collapsed and started expanding
+    fn expand(&self, _key: KeyType) -> bool {
+        false
+    }
 
-            tree
-        });
+    fn parent(&self, _key: KeyType) -> Option<KeyType> {
+        None
+    }
 
-        tree.ctrl.add_subscriber(tree.clone());
+    // Expands the ancestors of `key`, returns `false` for unknown keys
+    fn reveal(&self, _key: KeyType) -> bool {
+        false
+    }
+
+    fn expand_all(&self) {}
 
-        tree
+    fn collapse_all(&self) {}
+
+    // Whether every loaded expandable node is expanded
+    fn all_expanded(&self) -> bool {
+        false
     }
 
-    fn handle_click(&self, ev: web_sys::MouseEvent) {
-        tracing::info!("Handle click event");
+    // Position of `key` in the visible list, used for updating single rows
+    fn index_of(&self, _key: KeyType) -> Option<usize> {
+        None
+    }
 
-        let target = ev.target().unwrap().unchecked_into::<HtmlElement>();
-        if let Some(item) = target.closest("[data-key]").unwrap() {
-            let key = item.get_attribute("data-key").unwrap();
-            let key = key.parse::<usize>().unwrap();
-            tracing::info!("Handle click event for key {}", key);
+    fn is_frozen(&self) -> bool {
+        false
+    }
 
-            ev.prevent_default();
-            ev.stop_propagation();
-            self.ctrl.handle_click(key);
-        }
+    fn selected_keys(&self) -> Vec<KeyType> {
+        vec![]
     }
 
-    pub fn update_size(&self, width: usize, height: usize) {
-        self.state.borrow_mut().size = (width, height);
-        self.update();
+    fn is_selected(&self, _key: KeyType) -> bool {
+        false
     }
 
-    pub fn update_scroll(&self, offset_top: usize) {
-        self.state.borrow_mut().offset = offset_top;
-        self.update();
+    // Called for clicks on the row checkbox
+    fn toggle_checked(&self, _key: KeyType) {}
+
+    fn checked_keys(&self) -> Vec<KeyType> {
+        vec![]
     }
 
-    #[inline]
-    pub fn calc_shift(&self, item: &dyn TreeItem) -> usize {
-        // TODO: Make those offsets customizable
-        let mut offset = (item.depth()) as usize * 16 + 10;
-        if item.expandable() {
-            offset -= 16
-        }
+    // Whether the top level items are still being loaded
+    fn is_loading(&self) -> bool {
+        false
+    }
 
-        offset
+    // Asked on every `dragover`, rows that can't take the drop show no
+    // indicator
+    fn can_drop(&self, _key: KeyType, _target: DropTarget) -> bool {
+        false
     }
 
-    pub fn update(&self) {
-        const LABEL: &'static str = "Tree::update";
-        web_sys::console::time_with_label(LABEL);
+    fn handle_drop(&self, _key: KeyType, _target: DropTarget) {}
 
-        if let Ok(value) = Reflect::get(&web_sys::window().unwrap(), &"__debug".into()) {
-            if value.is_truthy() {
-                panic!("__debug")
-            }
+    // `None` once the pointer is off the rows
+    fn handle_hover(&self, _key: Option<KeyType>) {}
+}
+
+// Moves focus for arrow keys and returns the row that should be focused,
+// `None` means the key isn't handled
+pub fn apply_arrow_key(
+    ctrl: &dyn TreeController,
+    key: &str,
+    focused: Option<KeyType>,
+) -> Option<KeyType> {
+    let count = ctrl.count();
+    if count == 0 {
+        return None;
+    }
+
+    let index = focused.and_then(|key| ctrl.index_of(key));
+    match key {
+        "ArrowDown" => {
+            let next = index.map_or(0, |index| (index + 1).min(count - 1));
+            Some(ctrl.item(next).key())
+        }
+        "ArrowUp" => {
+            let prev = index.map_or(0, |index| index.saturating_sub(1));
+            Some(ctrl.item(prev).key())
         }
+        "ArrowRight" => {
+            let focused = focused?;
+            if ctrl.expand(focused) {
+                return Some(focused);
+            }
 
-        let mut state = self.state.borrow_mut();
-        // for splitting borrows
-        let state = &mut *state;
+            // an expanded node moves on to its first child
+            let child = index
+                .map(|index| index + 1)
+                .filter(|next| *next < count)
+                .map(|next| ctrl.item(next).key())
+                .filter(|child| ctrl.parent(*child) == Some(focused));
 
-        let count = self.ctrl.count();
-        let offset = state.offset;
-        let size = state.size;
+            Some(child.unwrap_or(focused))
+        }
+        "ArrowLeft" => {
+            let focused = focused?;
+            if ctrl.collapse(focused) {
+                return Some(focused);
+            }
 
-        state.count = count;
+            Some(ctrl.parent(focused).unwrap_or(focused))
+        }
+        _ => None,
+    }
+}
 
-        // Update can happen because of:
-        //  scroll
-        //  resize
-        //  change items [diff can work]
+// Home/End jump to the first and last row, PageUp/PageDown move focus by
+// `page` rows and stop at the ends. `None` means the key isn't handled
+pub fn apply_page_key(
+    ctrl: &dyn TreeController,
+    key: &str,
+    focused: Option<KeyType>,
+    page: usize,
+) -> Option<KeyType> {
+    let count = ctrl.count();
+    if count == 0 {
+        return None;
+    }
 
-        let first_visible = offset / self.item_height;
-        let visible_count = size.1 / self.item_height + 2;
+    let index = focused.and_then(|key| ctrl.index_of(key));
+    let next = match key {
+        "Home" => 0,
+        "End" => count - 1,
+        "PageDown" => index.map_or(0, |index| (index + page).min(count - 1)),
+        "PageUp" => index.map_or(0, |index| index.saturating_sub(page)),
+        _ => return None,
+    };
 
-        self.scroll
-            .set_attribute(
-                "style",
-                &format!("height: {}px", self.item_height * state.count),
-            )
-            .unwrap();
+    Some(ctrl.item(next).key())
+}
 
-        let rendered = &mut state.rendered;
+// The next visible row whose title starts with `prefix`, ignoring case and
+// wrapping around. A prefix that grows can keep the focused row, a single
+// character (or the same one typed again) moves past it
+pub fn typeahead_match(
+    ctrl: &dyn TreeController,
+    prefix: &str,
+    focused: Option<KeyType>,
+) -> Option<KeyType> {
+    let count = ctrl.count();
+    let first = prefix.chars().next()?;
 
-        let right = state.count.min(first_visible + visible_count);
-        let range = first_visible..right;
+    let repeated = prefix.chars().all(|ch| ch == first);
+    let prefix = if repeated {
+        first.to_lowercase().collect::<String>()
+    } else {
+        prefix.to_lowercase()
+    };
 
-        let mut visited = BTreeSet::<KeyType>::new();
+    let start = match focused.and_then(|key| ctrl.index_of(key)) {
+        Some(index) if repeated => index + 1,
+        Some(index) => index,
+        None => 0,
+    };
 
-        for (i, index) in range.enumerate() {
-            let item = self.ctrl.item(index);
-            let key = item.key();
+    (0..count)
+        .map(|offset| ctrl.item((start + offset) % count))
+        .find(|item| item.title().to_lowercase().starts_with(&prefix))
+        .map(|item| item.key())
+}
 
-            visited.insert(key);
+// Characters typed in quick succession, a pause starts a new prefix
+#[derive(Default)]
+struct Typeahead {
+    buffer: String,
+    last: f64,
+}
 
-            let y = (first_visible + i) * self.item_height;
-            if let Some(rendered) = rendered.get_mut(&key) {
-                rendered.update_style(y, self.calc_shift(&*item));
-                rendered.update_item(&*item);
-            } else {
-                let rendered_item = if let Some(mut rendered) = state.pool.pop() {
-                    rendered.update_item(&*item);
-                    rendered.update_style(y, self.calc_shift(&*item));
-                    rendered
-                } else {
-                    RenderedItem::render(&*item, y, self.calc_shift(&*item))
-                };
+const TYPEAHEAD_TIMEOUT: f64 = 700.0;
 
-                self.scroll.append_child(&rendered_item.container).unwrap();
-                rendered.insert(item.key(), rendered_item);
-            }
+impl Typeahead {
+    fn push(&mut self, ch: char, now: f64) -> &str {
+        if now - self.last > TYPEAHEAD_TIMEOUT {
+            self.buffer.clear();
         }
 
-        for (_key, item) in rendered.extract_if(|key, _| !visited.contains(&key)) {
-            // pool
-            item.container.remove();
-            state.pool.push(item)
-        }
+        self.last = now;
+        self.buffer.push(ch);
+        &self.buffer
+    }
 
-        web_sys::console::time_end_with_label(LABEL);
+    fn is_empty(&self, now: f64) -> bool {
+        self.buffer.is_empty() || now - self.last > TYPEAHEAD_TIMEOUT
     }
 }
 
-impl TreeSubscriber for TreeView {
-    fn update_all(&self) {
-        TreeView::update(self)
+// Printable keys without modifiers, named keys like "Tab" are longer than a
+// character
+fn typed_char(ev: &web_sys::KeyboardEvent) -> Option<char> {
+    if ev.ctrl_key() || ev.meta_key() || ev.alt_key() {
+        return None;
+    }
+
+    let key = ev.key();
+    let mut chars = key.chars();
+    let ch = chars.next()?;
+    chars.next().is_none().then_some(ch)
+}
+
+// Roving tabindex: the focused row is the only one Tab reaches, the others
+// are focused by the keyboard navigation
+fn row_tabindex(tab_stop: bool) -> &'static str {
+    if tab_stop {
+        "0"
+    } else {
+        "-1"
     }
+}
 
-    fn update_item(&self, _key: usize) {
-        // TODO: More efficient selective update
-        TreeView::update(self)
+pub fn toggle_expand_all(ctrl: &dyn TreeController) {
+    if ctrl.all_expanded() {
+        ctrl.collapse_all()
+    } else {
+        ctrl.expand_all()
     }
 }
 
-pub struct RenderedItem {
-    container: Element,
-    hash: u64,
-    expandable: bool,
-    expanded: bool,
-    arrow: Element,
-    icon: Element,
-    text: Text,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscapeAction {
+    ClearFilter,
+    Collapse,
+    FocusParent,
 }
 
-impl RenderedItem {
-    pub fn render(item: &dyn TreeItem, y: usize, x: usize) -> Self {
-        let document = web_sys::window().unwrap().document().unwrap();
+pub const DEFAULT_ESCAPE_ACTIONS: &[EscapeAction] = &[
+    EscapeAction::ClearFilter,
+    EscapeAction::Collapse,
+    EscapeAction::FocusParent,
+];
 
-        let container = document.create_element("div").unwrap();
-        container.set_attribute("class", "tree-item").unwrap();
-        container
-            .set_attribute("data-key", &item.key().to_string())
-            .unwrap();
+// Applies the first action that has an effect and returns it together with
+// the key that should be focused afterwards
+pub fn apply_escape(
+    ctrl: &dyn TreeController,
+    actions: &[EscapeAction],
+    focused: Option<KeyType>,
+) -> Option<(EscapeAction, Option<KeyType>)> {
+    for action in actions {
+        let applied = match action {
+            EscapeAction::ClearFilter => ctrl.clear_filter(),
+            EscapeAction::Collapse => focused.is_some_and(|key| ctrl.collapse(key)),
+            EscapeAction::FocusParent => {
+                if let Some(parent) = focused.and_then(|key| ctrl.parent(key)) {
+                    return Some((*action, Some(parent)));
+                }
 
-        let expanded = item.expanded();
-        let expandable = item.expandable();
+                false
+            }
+        };
 
-        let arrow = document.create_element("span").unwrap();
-        if !expandable {
-            arrow.set_attribute("style", "display: none").unwrap();
+        if applied {
+            return Some((*action, focused));
         }
+    }
 
-        arrow
-            .set_attribute("class", Self::expanded_classname(expanded))
-            .unwrap();
+    None
+}
 
-        container.append_child(&arrow).unwrap();
+// Titles of the selected rows in visible order, indented with tabs by
+// depth. Without a selection the focused row is copied
+pub fn copy_text(ctrl: &dyn TreeController, focused: Option<KeyType>) -> Option<String> {
+    let mut keys = ctrl.selected_keys();
+    if keys.is_empty() {
+        keys.extend(focused);
+    }
 
-        let icon = document.create_element("span").unwrap();
-        let icon_class = item.icon();
-        if !icon_class.is_empty() {
-            icon.set_attribute("class", &icon_class).unwrap();
-        }
+    let mut indices = keys
+        .into_iter()
+        .filter_map(|key| ctrl.index_of(key))
+        .collect::<Vec<_>>();
 
-        container.append_child(&icon).unwrap();
+    if indices.is_empty() {
+        return None;
+    }
 
-        let text_span = document.create_element("span").unwrap();
-        let text = document.create_text_node(&item.title());
-        text_span.append_child(&text).unwrap();
-        container.append_child(&text_span).unwrap();
+    indices.sort_unstable();
 
-        let hash = item.hash();
-        let mut this = Self {
-            container,
-            expandable,
-            arrow,
-            expanded,
-            icon,
-            text,
-            hash,
-        };
+    let lines = indices
+        .into_iter()
+        .map(|index| {
+            let item = ctrl.item(index);
+            let indent = "\t".repeat(item.depth().saturating_sub(1) as usize);
+            format!("{}{}", indent, &*item.title())
+        })
+        .collect::<Vec<_>>();
+
+    Some(lines.join("\n"))
+}
 
-        this.update_style(y, x);
+// Estimated width of a row, the indentation plus one slot for the icon (or
+// the trailing arrow) and the title. The widest row rendered so far sets the
+// min width of the scroll container, so deep trees scroll horizontally
+// instead of overflowing
+pub fn row_width(
+    item: &dyn TreeItem,
+    arrow_position: ArrowPosition,
+    indent: IndentConfig,
+    char_width: usize,
+) -> usize {
+    let title = item.title().chars().count() * char_width;
+    row_shift(item, arrow_position, indent) + indent.arrow_width + title
+}
 
-        this
+// Rows pinned to the top of the viewport with their y inside the sticky
+// lane: the ancestors of the first visible row, outermost first. Once the
+// end of a subtree scrolls up to its header, the header is pushed out
+pub fn sticky_ancestors(
+    ctrl: &dyn TreeController,
+    offsets: &RowOffsets,
+    offset: usize,
+) -> Vec<(usize, isize)> {
+    if ctrl.count() == 0 {
+        return vec![];
     }
 
-    fn expanded_classname(expanded: bool) -> &'static str {
-        if expanded {
-            "iconoir-nav-arrow-down"
+    let chain = ancestor_rows(ctrl, offsets.index_at(offset));
+    pin_ancestors(&chain, offsets, offset)
+}
+
+// Rows of the ancestors of the row at `index`, top level first
+fn ancestor_rows(ctrl: &dyn TreeController, index: usize) -> Vec<(usize, Rc<dyn TreeItem>)> {
+    let mut chain = vec![];
+    let mut key = ctrl.item(index).key();
+    while let Some(parent) = ctrl.parent(key) {
+        chain.extend(ctrl.index_of(parent).map(|index| (index, ctrl.item(index))));
+        key = parent;
+    }
+
+    chain.reverse();
+    chain
+}
+
+fn pin_ancestors(
+    chain: &[(usize, Rc<dyn TreeItem>)],
+    offsets: &RowOffsets,
+    offset: usize,
+) -> Vec<(usize, isize)> {
+    let mut top = 0;
+    chain
+        .iter()
+        .map(|(index, item)| {
+            let height = offsets.height(*index);
+            let end = offsets.offset(index + 1 + item.visible_children_len());
+            let y = (top as isize).min(end as isize - (offset + height) as isize);
+
+            top += height;
+            (*index, y)
+        })
+        .collect()
+}
+
+// Everything a paint needs from the controller. It is read before the state
+// is borrowed for the paint, so a controller that calls back into the view
+// while answering never finds the state borrowed
+struct FrameRows {
+    offset: usize,
+    // index, item and whether it is selected
+    rows: Vec<(usize, Rc<dyn TreeItem>, bool)>,
+    ancestors: Vec<(usize, Rc<dyn TreeItem>)>,
+}
+
+impl FrameRows {
+    fn read(ctrl: &dyn TreeController, state: &RefCell<TreeState>, sticky: bool) -> Self {
+        let count = ctrl.count();
+        let (range, offset, first) = {
+            let mut state = state.borrow_mut();
+            state.count = count;
+            let first = state.offsets.index_at(state.offset);
+            (state.visible_range(), state.offset, first)
+        };
+
+        // the header of the group the viewport is in must stay rendered even
+        // when its own row is scrolled out
+        let header = ctrl
+            .sticky_header(range.start)
+            .filter(|index| *index < range.start);
+
+        let rows = header
+            .into_iter()
+            .chain(range)
+            .map(|index| {
+                let item = ctrl.item(index);
+                let selected = ctrl.is_selected(item.key());
+                (index, item, selected)
+            })
+            .collect();
+
+        let ancestors = if sticky && count > 0 {
+            ancestor_rows(ctrl, first)
         } else {
-            "iconoir-nav-arrow-right"
+            vec![]
+        };
+
+        FrameRows {
+            offset,
+            rows,
+            ancestors,
         }
     }
+}
 
-    pub fn toggle_expanded(&mut self) {
-        self.expanded = !self.expanded;
-        let classname = Self::expanded_classname(self.expanded);
-        self.arrow.set_attribute("class", classname).unwrap();
-    }
+// Vertical extent in which a group header at `index` sticks to the top of
+// the viewport, the header is pushed out once its last child scrolls by
+pub fn sticky_range(index: usize, children_len: usize, offsets: &RowOffsets) -> Range<usize> {
+    offsets.offset(index)..offsets.offset(index + children_len)
+}
+
+// Style of the row progress bar, a finished load hides the bar
+pub fn progress_style(progress: Option<f32>) -> Option<String> {
+    let progress = progress.filter(|p| *p < 1.0)?;
+    Some(format!("width: {}%", progress.max(0.0) * 100.0))
+}
+
+// Splits the title into plain and highlighted (`true`) parts. Ranges are
+// sorted and merged, parts outside of the title or not on a char boundary
+// are ignored
+pub fn title_spans<'a>(title: &'a str, ranges: &[Range<usize>]) -> Vec<(&'a str, bool)> {
+    let mut ranges = ranges
+        .iter()
+        .filter(|r| {
+            !r.is_empty() && title.is_char_boundary(r.start) && title.is_char_boundary(r.end)
+        })
+        .filter(|r| r.end <= title.len())
+        .cloned()
+        .collect::<Vec<_>>();
 
-    pub fn update_style(&mut self, y: usize, x: usize) {
-        // FIXME: bump-allocate this
-        let style = format!("top: {}px; padding-left: {}px", y, x);
-        self.container.set_attribute("style", &style).unwrap();
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
     }
 
-    pub fn update_item(&mut self, item: &dyn TreeItem) {
-        if self.expandable != item.expandable() {
-            self.expandable = item.expandable();
-            if self.expandable {
-                self.arrow.remove_attribute("style").unwrap();
-            } else {
-                self.arrow.set_attribute("style", "display: none").unwrap();
-            }
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for range in merged {
+        if range.start > offset {
+            spans.push((&title[offset..range.start], false));
         }
 
-        if self.expanded != item.expanded() {
-            self.toggle_expanded()
+        spans.push((&title[range.clone()], true));
+        offset = range.end;
+    }
+
+    if offset < title.len() {
+        spans.push((&title[offset..], false));
+    }
+
+    spans
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CheckboxPolicy {
+    All,
+    LeavesOnly,
+    #[default]
+    None,
+}
+
+impl CheckboxPolicy {
+    pub fn shows_checkbox(&self, item: &dyn TreeItem) -> bool {
+        match self {
+            CheckboxPolicy::All => true,
+            CheckboxPolicy::LeavesOnly => !item.expandable(),
+            CheckboxPolicy::None => false,
         }
+    }
+}
 
-        let hash = item.hash();
-        if hash == self.hash {
-            return;
+#[derive(Default)]
+pub struct TreeState {
+    count: usize,
+    rendered: HashMap<KeyType, RenderedItem>,
+    pool: Vec<RenderedItem>,
+    offset: usize,
+    offset_left: usize,
+    size: (usize, usize),
+    focused: Option<KeyType>,
+    hovered: Option<KeyType>,
+    offsets: RowOffsets,
+    sticky_rows: Vec<RenderedItem>,
+    leaving: LeavingRows<RenderedItem>,
+    // Twice the visible rows when not set
+    max_pool_size: Option<usize>,
+    // Rebuilt from the controller on the next update
+    offsets_stale: bool,
+    // Widest row measured since the list last changed, so the min width
+    // doesn't shrink and grow while scrolling
+    content_width: usize,
+    // Number of rendered rows refreshed so far, lets tests check that
+    // single item updates don't touch other rows
+    touched: usize,
+}
+
+impl TreeState {
+    fn reclaim(&mut self, mut row: RenderedItem) {
+        row.container.remove();
+        // a row that still looks like it's leaving isn't reused
+        match row.set_leaving(false) {
+            Ok(()) => self.pool_row(row),
+            Err(err) => report(Err(err)),
         }
+    }
 
-        self.hash = hash;
+    // Rows beyond the limit are dropped, their elements are already
+    // detached
+    fn pool_row(&mut self, mut row: RenderedItem) {
+        report(row.set_hover(false));
+        row.recycle();
+        let limit = self.pool_limit();
+        push_bounded(&mut self.pool, row, limit);
+    }
 
-        self.container
-            .set_attribute("data-key", &item.key().to_string())
-            .unwrap();
+    fn pool_limit(&self) -> usize {
+        self.max_pool_size
+            .unwrap_or_else(|| self.visible_range().len() * 2)
+    }
 
-        self.icon.set_attribute("class", &*item.icon()).unwrap();
+    fn rendered_keys(&self, keys: &[KeyType]) -> Vec<KeyType> {
+        keys.iter()
+            .copied()
+            .filter(|key| self.rendered.contains_key(key))
+            .collect()
+    }
 
-        self.text.set_data(&item.title());
+    // Rows the last paint didn't reach, scrolled out or gone from the list
+    fn take_unvisited(&mut self, visited: &BTreeSet<KeyType>) -> Vec<(KeyType, RenderedItem)> {
+        self.rendered
+            .extract_if(|key, _| !visited.contains(key))
+            .collect()
     }
-}
+
+    // The focused row is the only tab stop while it's rendered, the
+    // container takes over otherwise
+    fn row_tab_stop(&self) -> bool {
+        self.focused
+            .is_some_and(|key| self.rendered.contains_key(&key))
+    }
+
+    fn touch(&mut self, key: KeyType) -> Option<&mut RenderedItem> {
+        let rendered = self.rendered.get_mut(&key)?;
+        self.touched += 1;
+        Some(rendered)
+    }
+
+    // Returns `true` if the size changed, only the latest size of a burst of
+    // resizes is kept for the next paint
+    fn set_size(&mut self, width: usize, height: usize) -> bool {
+        std::mem::replace(&mut self.size, (width, height)) != (width, height)
+    }
+
+    // Returns `true` only when the vertical offset changed, horizontal
+    // scrolling never affects the visible range
+    fn set_scroll(&mut self, offset_top: usize, offset_left: usize) -> bool {
+        self.offset_left = offset_left;
+        std::mem::replace(&mut self.offset, offset_top) != offset_top
+    }
+
+    // Rows of the default height that fit into the viewport, at least one
+    fn page_size(&self, item_height: usize) -> usize {
+        (self.size.1 / item_height.max(1)).max(1)
+    }
+
+    fn visible_range(&self) -> Range<usize> {
+        let first_visible = self.offsets.index_at(self.offset);
+        let last_visible = self.offsets.index_at(self.offset + self.size.1);
+
+        first_visible..self.count.min(last_visible + 2)
+    }
+
+    // Returns the previously hovered row if the hovered row changed
+    fn set_hovered(&mut self, key: Option<KeyType>) -> Option<Option<KeyType>> {
+        if self.hovered == key {
+            return None;
+        }
+
+        Some(std::mem::replace(&mut self.hovered, key))
+    }
+
+    // Changes after the rendered rows only move rows nobody sees
+    fn is_below_window(&self, change: &TreeChange) -> bool {
+        change.start() >= self.visible_range().end
+    }
+
+    // Scroll offset that keeps the first visible row in place when rows
+    // above it were replaced, `None` if the change reaches into the window.
+    // The offsets are still the ones from before the change
+    fn anchored_offset(&self, change: &TreeChange, inserted_height: usize) -> Option<usize> {
+        let first_visible = self.offsets.index_at(self.offset);
+        if self.offsets.is_empty() || change.removed.end > first_visible {
+            return None;
+        }
+
+        let removed_height =
+            self.offsets.offset(change.removed.end) - self.offsets.offset(change.removed.start);
+
+        Some((self.offset + inserted_height).saturating_sub(removed_height))
+    }
+}
+
+// Rows kept in the DOM while their leave transition plays. Rows are moved
+// to the pool once the transition ends, or when their batch expires for
+// rows that don't have a transition at all
+pub struct LeavingRows<T> {
+    generation: usize,
+    rows: Vec<(usize, KeyType, T)>,
+}
+
+impl<T> Default for LeavingRows<T> {
+    fn default() -> Self {
+        LeavingRows {
+            generation: 0,
+            rows: vec![],
+        }
+    }
+}
+
+impl<T> LeavingRows<T> {
+    // Starts a batch of rows leaving together
+    pub fn next_generation(&mut self) -> usize {
+        self.generation += 1;
+        self.generation
+    }
+
+    pub fn push(&mut self, key: KeyType, row: T) {
+        self.rows.push((self.generation, key, row))
+    }
+
+    pub fn finish(&mut self, key: KeyType) -> Option<T> {
+        let position = self.rows.iter().position(|(_, k, _)| *k == key)?;
+        Some(self.rows.remove(position).2)
+    }
+
+    // Rows of `generation` and older batches
+    pub fn expire(&mut self, generation: usize) -> Vec<T> {
+        let (expired, rows): (Vec<_>, Vec<_>) = std::mem::take(&mut self.rows)
+            .into_iter()
+            .partition(|(g, _, _)| *g <= generation);
+
+        self.rows = rows;
+        expired.into_iter().map(|(_, _, row)| row).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+// Collapses paint requests made within one frame into a single paint
+#[derive(Debug, Default)]
+pub struct FrameBatch {
+    pending: Cell<bool>,
+}
+
+impl FrameBatch {
+    // Returns `true` if no paint was pending, so the caller has to schedule
+    // a frame
+    pub fn request(&self) -> bool {
+        !self.pending.replace(true)
+    }
+
+    // Returns `true` if a paint is still wanted. A paint that happened
+    // synchronously in the meantime takes the request as well
+    pub fn take(&self) -> bool {
+        self.pending.replace(false)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.get()
+    }
+}
+
+// Returns `false` if the pool is full and `row` was dropped
+pub fn push_bounded<T>(pool: &mut Vec<T>, row: T, limit: usize) -> bool {
+    if pool.len() >= limit {
+        return false;
+    }
+
+    pool.push(row);
+    true
+}
+
+// Rows created up front, so the first scroll doesn't have to build them
+const POOL_WARMUP: usize = 8;
+
+// Leaving rows without a transition are reclaimed after this long
+const LEAVE_TIMEOUT_MS: i32 = 1000;
+
+// Only listened to with drag and drop enabled
+const DRAG_EVENTS: [&str; 5] = ["dragstart", "dragover", "dragleave", "drop", "dragend"];
+
+// The row being dragged and the folder it hovers over
+#[derive(Debug, Default)]
+struct DragState {
+    dragged: Option<KeyType>,
+    hovered: Option<(KeyType, f64)>,
+}
+
+impl DragState {
+    // Returns `true` once the folder `key` has been hovered for `delay`
+    // milliseconds. Moving to another row or off the rows (`None`) starts
+    // over
+    fn hover(&mut self, key: Option<KeyType>, now: f64, delay: f64) -> bool {
+        let Some(key) = key else {
+            self.hovered = None;
+            return false;
+        };
+
+        match &mut self.hovered {
+            Some((hovered, since)) if *hovered == key => {
+                if now - *since < delay {
+                    return false;
+                }
+
+                // fires once per hover
+                *since = f64::INFINITY;
+                true
+            }
+            _ => {
+                self.hovered = Some((key, now));
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArrowPosition {
+    #[default]
+    Leading,
+    Trailing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndentConfig {
+    pub per_level: usize,
+    pub base: usize,
+    pub arrow_width: usize,
+}
+
+impl Default for IndentConfig {
+    fn default() -> Self {
+        IndentConfig {
+            per_level: 16,
+            base: 10,
+            arrow_width: 16,
+        }
+    }
+}
+
+// Left padding of a row, leading arrows sit in the indentation of their
+// own level while trailing ones are rendered after the title
+pub fn row_shift(
+    item: &dyn TreeItem,
+    arrow_position: ArrowPosition,
+    indent: IndentConfig,
+) -> usize {
+    let offset = (item.depth()) as usize * indent.per_level + indent.base;
+    if item.expandable() && arrow_position == ArrowPosition::Leading {
+        offset.saturating_sub(indent.arrow_width)
+    } else {
+        offset
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TreeViewOptions {
+    pub item_height: usize,
+    pub arrow_position: ArrowPosition,
+    pub indent: IndentConfig,
+    // Average glyph width used to estimate how wide the titles are
+    pub char_width: usize,
+    // Pins the ancestors of the first visible row above it, costs a second
+    // lane of rows
+    pub sticky_ancestors: bool,
+    // Rows that appear or disappear because of expand/collapse get the
+    // `tree-item-entering`/`tree-item-leaving` classes for a transition
+    pub animations: bool,
+    // Detached rows kept for reuse, twice the visible rows by default
+    pub max_pool_size: Option<usize>,
+    // Rows can be dragged onto each other, the controller decides where
+    // they can go
+    pub drag_and_drop: bool,
+    // Milliseconds a collapsed folder has to be hovered during a drag
+    // before it expands, `None` never expands
+    pub drag_expand_delay: Option<u32>,
+    pub theme: TreeTheme,
+    pub row_renderer: Option<Rc<dyn RowRenderer>>,
+}
+
+impl Default for TreeViewOptions {
+    fn default() -> Self {
+        TreeViewOptions {
+            item_height: 24,
+            arrow_position: ArrowPosition::Leading,
+            indent: Default::default(),
+            char_width: 8,
+            sticky_ancestors: false,
+            animations: false,
+            max_pool_size: None,
+            drag_and_drop: false,
+            drag_expand_delay: Some(600),
+            theme: Default::default(),
+            row_renderer: None,
+        }
+    }
+}
+
+// Class names of the elements the view creates, for other icon sets or CSS
+// scopes. The state classes without a field keep their `tree-item-` names
+#[derive(Clone, Debug)]
+pub struct TreeTheme {
+    pub container: String,
+    pub scroll: String,
+    pub item: String,
+    pub arrow_expanded: String,
+    pub arrow_collapsed: String,
+    pub selected: String,
+    pub focused: String,
+    // Replaces the arrow class swap, the arrow element is left to the
+    // renderer
+    pub arrow_renderer: Option<ArrowRenderer>,
+}
+
+// Called with the arrow element of a row and whether it's expanded, when the
+// row is rendered and whenever the row's expanded state changes, including
+// rows reused for another item
+pub type ArrowCallback = Callback<dyn Fn(&Element, bool)>;
+
+#[derive(Clone)]
+pub struct ArrowRenderer(pub ArrowCallback);
+
+impl std::fmt::Debug for ArrowRenderer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ArrowRenderer")
+    }
+}
+
+impl TreeTheme {
+    pub fn arrow(&self, expanded: bool) -> &str {
+        if expanded {
+            &self.arrow_expanded
+        } else {
+            &self.arrow_collapsed
+        }
+    }
+
+    fn render_arrow(&self, arrow: &Element, expanded: bool) -> Result<(), TreeError> {
+        match &self.arrow_renderer {
+            Some(ArrowRenderer(render)) => render(arrow, expanded),
+            None => arrow.set_attribute("class", self.arrow(expanded))?,
+        }
+
+        Ok(())
+    }
+}
+
+// Theme values may hold several classes, `classList` only takes one token
+// at a time
+fn toggle_classes(element: &Element, classes: &str, force: bool) -> Result<(), TreeError> {
+    let class_list = element.class_list();
+    for class in classes.split_whitespace() {
+        class_list.toggle_with_force(class, force)?;
+    }
+
+    Ok(())
+}
+
+impl Default for TreeTheme {
+    fn default() -> Self {
+        TreeTheme {
+            container: "tree".into(),
+            scroll: "tree-scroll".into(),
+            item: "tree-item".into(),
+            arrow_expanded: "iconoir-nav-arrow-down".into(),
+            arrow_collapsed: "iconoir-nav-arrow-right".into(),
+            selected: "tree-item-selected".into(),
+            focused: "tree-item-focused".into(),
+            arrow_renderer: None,
+        }
+    }
+}
+
+pub type CopyCallback = Callback<dyn Fn(String)>;
+
+pub struct TreeView {
+    this: Weak<TreeView>,
+    state: RefCell<TreeState>,
+
+    pub container: HtmlElement,
+    pub scroll: HtmlElement,
+    sticky_lane: Option<Element>,
+    parent: RefCell<Option<Element>>,
+    loading: Element,
+    expand_toggle: RefCell<Option<Element>>,
+    item_height: Cell<usize>,
+    arrow_position: ArrowPosition,
+    indent: Cell<IndentConfig>,
+    char_width: usize,
+    animations: bool,
+    // Set by layout changes, scrolling never animates rows
+    animate_next: Cell<bool>,
+    paint: FrameBatch,
+    checkbox_policy: Cell<CheckboxPolicy>,
+    escape_actions: RefCell<Vec<EscapeAction>>,
+    on_copy: RefCell<Option<CopyCallback>>,
+    // Row being renamed and its input
+    edit: RefCell<Option<(KeyType, HtmlInputElement)>>,
+    drag_and_drop: bool,
+    drag: RefCell<DragState>,
+    typeahead: RefCell<Typeahead>,
+    drag_expand_delay: Cell<Option<u32>>,
+    drop_indicator: Element,
+    container_tab_stop: Cell<bool>,
+    theme: Rc<TreeTheme>,
+    row_renderer: Option<Rc<dyn RowRenderer>>,
+
+    ctrl: Rc<dyn TreeController>,
+
+    #[allow(unused)]
+    on_resize: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_click: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_dbl_click: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_context_menu: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_focus_out: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_transition_end: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_scroll: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_keydown: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_mouse_over: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_mouse_out: Closure<dyn Fn(JsValue)>,
+    #[allow(unused)]
+    on_drag: [Closure<dyn Fn(JsValue)>; 5],
+    #[allow(unused)]
+    observer: JsValue,
+}
+
+impl Drop for TreeView {
+    fn drop(&mut self) {
+        self.container.remove();
+        unobserve(&self.observer)
+    }
+}
+
+impl TreeView {
+    pub fn render(handle: Rc<dyn TreeController>) -> Rc<TreeView> {
+        Self::render_with_options(handle, Default::default())
+    }
+
+    // Panics if the elements of the tree can't be created, see
+    // `try_render_with_options`
+    pub fn render_with_options(
+        handle: Rc<dyn TreeController>,
+        options: TreeViewOptions,
+    ) -> Rc<TreeView> {
+        match Self::try_render_with_options(handle, options) {
+            Ok(tree) => tree,
+            Err(err) => panic!("Failed to render the tree: {:?}", err),
+        }
+    }
+
+    pub fn try_render_with_options(
+        handle: Rc<dyn TreeController>,
+        options: TreeViewOptions,
+    ) -> Result<Rc<TreeView>, TreeError> {
+        let theme = Rc::new(options.theme.clone());
+        let document = document()?;
+        let container: HtmlElement = document.create_element("div")?.unchecked_into();
+        container.set_attribute("class", &theme.container)?;
+        container.set_attribute("role", "tree")?;
+        container.set_attribute("tabindex", "0")?;
+
+        let scroll: HtmlElement = document.create_element("div")?.unchecked_into();
+        scroll.set_attribute("class", &theme.scroll)?;
+
+        container.append_child(&scroll)?;
+
+        // sticks to the top of the container, rows inside are positioned
+        // relative to it
+        let sticky_lane = if options.sticky_ancestors {
+            let lane = document.create_element("div")?;
+            lane.set_attribute("class", "tree-sticky-lane")?;
+            // copies of rows that are in the tree already
+            lane.set_attribute("aria-hidden", "true")?;
+            lane.set_attribute("style", "position: sticky; top: 0; height: 0; z-index: 1")?;
+            container.insert_before(&lane, Some(&scroll))?;
+            Some(lane)
+        } else {
+            None
+        };
+
+        let loading = document.create_element("div")?;
+        loading.set_attribute("class", "tree-loading")?;
+        loading.set_text_content(Some("Loading…"));
+        container.append_child(&loading)?;
+
+        let drop_indicator = document.create_element("div")?;
+        drop_indicator.set_attribute("class", "tree-drop-indicator")?;
+        drop_indicator.set_attribute("style", "display: none")?;
+        scroll.append_child(&drop_indicator)?;
+
+        let tree = Rc::<TreeView>::new_cyclic(|this| {
+            let on_click: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_click(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_dbl_click: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_dbl_click(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_context_menu: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_context_menu(ev.unchecked_into());
+                    }
+                }
+            });
+
+            // unlike blur, focusout bubbles up from the edit input
+            let on_focus_out: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_focus_out(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_transition_end: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_transition_end(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_keydown: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_keydown(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let drag_listener = |handler: fn(&TreeView, web_sys::DragEvent)| {
+                let this = this.clone();
+                Closure::<dyn Fn(JsValue)>::new(move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        handler(&this, ev.unchecked_into());
+                    }
+                })
+            };
+
+            // in the order of `DRAG_EVENTS`
+            let on_drag = [
+                drag_listener(TreeView::handle_drag_start),
+                drag_listener(TreeView::handle_drag_over),
+                drag_listener(TreeView::handle_drag_leave),
+                drag_listener(TreeView::handle_drop),
+                drag_listener(TreeView::handle_drag_end),
+            ];
+
+            let on_mouse_over: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_mouse_over(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_mouse_out: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |ev: JsValue| {
+                    if let Some(this) = this.upgrade() {
+                        this.handle_mouse_out(ev.unchecked_into());
+                    }
+                }
+            });
+
+            let on_scroll: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |_: JsValue| {
+                    let Some(this) = this.upgrade() else {
+                        return;
+                    };
+
+                    let offset_top = this.container.scroll_top();
+                    let offset_left = this.container.scroll_left();
+                    this.update_scroll(offset_top as usize, offset_left as usize);
+                }
+            });
+
+            let on_resize: Closure<dyn Fn(JsValue)> = Closure::new({
+                let this = this.clone();
+                move |size: JsValue| {
+                    let dimension = |index| Reflect::get_u32(&size, index).ok()?.as_f64();
+                    let (Some(width), Some(height), Some(this)) =
+                        (dimension(0), dimension(1), this.upgrade())
+                    else {
+                        return;
+                    };
+
+                    this.update_size(width as usize, height as usize);
+                }
+            });
+
+            let observer = observe(&container, on_resize.as_ref().unchecked_ref());
+            let size = (0, 0);
+            let offset = 0;
+            let offset_left = 0;
+            // zero height rows would break the visible range math
+            let item_height = options.item_height.max(1);
+            let count = handle.count();
+
+            TreeView {
+                this: this.clone(),
+                ctrl: handle,
+                state: RefCell::new(TreeState {
+                    count,
+                    size,
+                    offset,
+                    offset_left,
+                    rendered: Default::default(),
+                    pool: vec![],
+                    focused: None,
+                    hovered: None,
+                    offsets: Default::default(),
+                    sticky_rows: vec![],
+                    leaving: Default::default(),
+                    max_pool_size: options.max_pool_size,
+                    offsets_stale: true,
+                    content_width: 0,
+                    touched: 0,
+                }),
+                scroll,
+                container,
+                parent: RefCell::new(None),
+                loading,
+                expand_toggle: RefCell::new(None),
+                sticky_lane,
+                observer,
+                on_resize,
+                on_scroll,
+                on_click,
+                on_dbl_click,
+                on_context_menu,
+                on_focus_out,
+                on_transition_end,
+                on_keydown,
+                on_mouse_over,
+                on_mouse_out,
+                on_drag,
+                item_height: Cell::new(item_height),
+                arrow_position: options.arrow_position,
+                char_width: options.char_width,
+                animations: options.animations,
+                animate_next: Cell::new(false),
+                paint: Default::default(),
+                indent: Cell::new(options.indent),
+                checkbox_policy: Cell::new(CheckboxPolicy::None),
+                escape_actions: RefCell::new(DEFAULT_ESCAPE_ACTIONS.to_vec()),
+                on_copy: RefCell::new(None),
+                edit: RefCell::new(None),
+                drag_and_drop: options.drag_and_drop,
+                drag: Default::default(),
+                typeahead: Default::default(),
+                drag_expand_delay: Cell::new(options.drag_expand_delay),
+                drop_indicator,
+                container_tab_stop: Cell::new(true),
+                theme,
+                row_renderer: options.row_renderer.clone(),
+            }
+        });
+
+        tree.listen()?;
+        tree.warm_pool();
+        tree.update_loading();
+        tree.update();
+        tree.ctrl.add_subscriber(tree.clone());
+
+        Ok(tree)
+    }
+
+    fn listen(&self) -> Result<(), TreeError> {
+        let listeners = [
+            ("click", &self.on_click),
+            ("dblclick", &self.on_dbl_click),
+            ("contextmenu", &self.on_context_menu),
+            ("focusout", &self.on_focus_out),
+            ("keydown", &self.on_keydown),
+            ("mouseover", &self.on_mouse_over),
+            ("mouseout", &self.on_mouse_out),
+            ("scroll", &self.on_scroll),
+        ];
+
+        let animations = self
+            .animations
+            .then_some(("transitionend", &self.on_transition_end));
+
+        let drag = DRAG_EVENTS
+            .into_iter()
+            .zip(&self.on_drag)
+            .filter(|_| self.drag_and_drop);
+
+        for (event, listener) in listeners.into_iter().chain(animations).chain(drag) {
+            self.container
+                .add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_click(&self, ev: web_sys::MouseEvent) {
+        tracing::info!("Handle click event");
+
+        let Some(target) = event_target(&ev) else {
+            return;
+        };
+
+        if within(&target, ".tree-item-edit") {
+            return;
+        }
+
+        if within(&target, ".tree-expand-toggle") {
+            ev.prevent_default();
+            toggle_expand_all(&*self.ctrl);
+            return;
+        }
+
+        if let Some(key) = event_key(&ev) {
+            tracing::info!("Handle click event for key {}", key);
+
+            // the checkbox toggles itself, the row stays as it is
+            if within(&target, ".tree-item-checkbox") {
+                ev.stop_propagation();
+                self.ctrl.toggle_checked(key);
+
+                // the controller may ignore the toggle, e.g. while frozen, so
+                // the box is written back from the last known state. A change
+                // it accepted reaches the row with its update
+                if let Some(row) = self.state.borrow().rendered.get(&key) {
+                    row.reset_checkbox();
+                }
+
+                return;
+            }
+
+            let modifiers = ClickModifiers {
+                toggle: ev.ctrl_key() || ev.meta_key(),
+                range: ev.shift_key(),
+            };
+
+            ev.prevent_default();
+            ev.stop_propagation();
+            self.ctrl.handle_click(key, modifiers);
+        }
+    }
+
+    fn handle_dbl_click(&self, ev: web_sys::MouseEvent) {
+        let Some(target) = event_target(&ev) else {
+            return;
+        };
+
+        if within(&target, ".tree-expand-toggle, .tree-item-edit") {
+            return;
+        }
+
+        if let Some(key) = event_key(&ev) {
+            ev.prevent_default();
+            ev.stop_propagation();
+            self.ctrl.handle_dbl_click(key);
+        }
+    }
+
+    // Right clicks outside of rows keep the browser menu
+    fn handle_context_menu(&self, ev: web_sys::MouseEvent) {
+        if let Some(key) = event_key(&ev) {
+            ev.prevent_default();
+            self.ctrl
+                .handle_context_menu(key, ev.client_x(), ev.client_y());
+        }
+    }
+
+    // Renders a header button that expands or collapses the whole tree
+    pub fn enable_expand_toggle(&self) {
+        if self.expand_toggle.borrow().is_some() {
+            return;
+        }
+
+        match self.render_expand_toggle() {
+            Ok(button) => {
+                self.expand_toggle.replace(Some(button));
+                self.update_expand_toggle();
+            }
+            Err(err) => tracing::error!("Failed to render the expand toggle: {:?}", err),
+        }
+    }
+
+    fn render_expand_toggle(&self) -> Result<Element, TreeError> {
+        let button = document()?.create_element("button")?;
+        button.set_attribute("type", "button")?;
+        button.set_attribute("class", "tree-header tree-expand-toggle")?;
+        self.container.insert_before(&button, Some(&self.scroll))?;
+        Ok(button)
+    }
+
+    fn update_expand_toggle(&self) {
+        if let Some(button) = &*self.expand_toggle.borrow() {
+            let label = if self.ctrl.all_expanded() {
+                "Collapse all"
+            } else {
+                "Expand all"
+            };
+
+            button.set_text_content(Some(label));
+        }
+    }
+
+    fn update_frozen(&self) {
+        let frozen = self.ctrl.is_frozen();
+        report(
+            self.container
+                .class_list()
+                .toggle_with_force("tree-frozen", frozen)
+                .map(drop),
+        );
+    }
+
+    // The indicator covers the view until the root children arrive
+    fn update_loading(&self) {
+        report(if self.ctrl.is_loading() {
+            self.loading.remove_attribute("style")
+        } else {
+            self.loading.set_attribute("style", "display: none")
+        });
+    }
+
+    fn handle_keydown(&self, ev: web_sys::KeyboardEvent) {
+        // keys typed into the edit input only finish the edit
+        if self.edit.borrow().is_some() {
+            if ev.key() == "Enter" || ev.key() == "Escape" {
+                ev.prevent_default();
+                self.end_edit(ev.key() == "Enter");
+                report(self.container.focus());
+            }
+
+            return;
+        }
+
+        // caps lock and shift report an upper case key
+        if (ev.ctrl_key() || ev.meta_key()) && ev.key().eq_ignore_ascii_case("c") {
+            if let Some(text) = copy_text(&*self.ctrl, self.focused()) {
+                ev.prevent_default();
+                self.copy(text);
+            }
+        } else if ev.key() == "Enter" {
+            if let Some(key) = self.focused() {
+                ev.prevent_default();
+                self.ctrl.handle_click(key, Default::default());
+            }
+        } else if let Some(focus) = apply_arrow_key(&*self.ctrl, &ev.key(), self.focused()) {
+            ev.prevent_default();
+            self.focus_row(focus);
+        } else if let Some(focus) =
+            apply_page_key(&*self.ctrl, &ev.key(), self.focused(), self.page_size())
+        {
+            ev.prevent_default();
+            self.focus_row(focus);
+        } else if ev.key() == "Escape" {
+            let focused = self.focused();
+            let applied = apply_escape(&*self.ctrl, &self.escape_actions.borrow(), focused);
+
+            if let Some((_, focus)) = applied {
+                ev.prevent_default();
+                if focus != focused {
+                    self.focus(focus);
+                }
+            }
+        } else if let Some(ch) = typed_char(&ev) {
+            self.handle_typeahead(&ev, ch);
+        }
+    }
+
+    fn handle_typeahead(&self, ev: &web_sys::KeyboardEvent, ch: char) {
+        let now = js_sys::Date::now();
+        let mut typeahead = self.typeahead.borrow_mut();
+
+        // space only continues a prefix, e.g. "my file"
+        if ch == ' ' && typeahead.is_empty(now) {
+            return;
+        }
+
+        ev.prevent_default();
+        let prefix = typeahead.push(ch, now).to_string();
+        drop(typeahead);
+
+        if let Some(focus) = typeahead_match(&*self.ctrl, &prefix, self.focused()) {
+            self.focus_row(focus);
+        }
+    }
+
+    // Moving focus away from the input commits the edit
+    fn handle_focus_out(&self, ev: web_sys::Event) {
+        let Some(target) = event_target(&ev) else {
+            return;
+        };
+
+        if target.class_list().contains("tree-item-edit") {
+            self.end_edit(true);
+        }
+    }
+
+    // Replaces the title of the row with an input, Enter or moving focus
+    // away commits the new title, Escape cancels. Returns `false` if there
+    // is no such node
+    pub fn begin_edit(&self, key: KeyType) -> bool {
+        if self.ctrl.is_frozen() {
+            return false;
+        }
+
+        self.end_edit(true);
+        if !self.scroll_to_key(key) {
+            return false;
+        }
+
+        let Some(index) = self.ctrl.index_of(key) else {
+            return false;
+        };
+
+        let title = self.ctrl.item(index).title().to_string();
+        let input = match self.render_edit_input(key, &title) {
+            Ok(Some(input)) => input,
+            Ok(None) => return false,
+            Err(err) => {
+                tracing::error!("Failed to begin an edit: {:?}", err);
+                return false;
+            }
+        };
+
+        self.edit.replace(Some((key, input.clone())));
+        report(input.focus());
+        input.select();
+        true
+    }
+
+    // `None` if the row isn't rendered
+    fn render_edit_input(
+        &self,
+        key: KeyType,
+        title: &str,
+    ) -> Result<Option<HtmlInputElement>, TreeError> {
+        let mut state = self.state.borrow_mut();
+        let Some(rendered) = state.rendered.get_mut(&key) else {
+            return Ok(None);
+        };
+
+        let input: HtmlInputElement = document()?.create_element("input")?.unchecked_into();
+        input.set_attribute("class", "tree-item-edit")?;
+        input.set_value(title);
+        rendered.begin_edit(&input)?;
+        Ok(Some(input))
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.edit.borrow().is_some()
+    }
+
+    fn end_edit(&self, commit: bool) {
+        // taken first, removing the focused input fires focusout again
+        let Some((key, input)) = self.edit.take() else {
+            return;
+        };
+
+        if let Some(rendered) = self.state.borrow_mut().rendered.get_mut(&key) {
+            report(rendered.end_edit());
+        }
+
+        if commit {
+            self.ctrl.handle_rename(key, input.value());
+        }
+    }
+
+    fn copy(&self, text: String) {
+        if let Some(on_copy) = self.on_copy.borrow().as_ref() {
+            return on_copy(text);
+        }
+
+        if let Some(window) = web_sys::window() {
+            let _ = window.navigator().clipboard().write_text(&text);
+        }
+    }
+
+    // Replaces writing to the clipboard on Ctrl/Cmd+C
+    pub fn set_on_copy(&self, on_copy: Option<CopyCallback>) {
+        self.on_copy.replace(on_copy);
+    }
+
+    pub fn set_checkbox_policy(&self, policy: CheckboxPolicy) {
+        self.checkbox_policy.set(policy);
+        self.update();
+    }
+
+    // Escape runs the first of `actions` that applies
+    pub fn set_escape_actions(&self, actions: Vec<EscapeAction>) {
+        self.escape_actions.replace(actions);
+    }
+
+    pub fn set_drag_expand_delay(&self, delay: Option<u32>) {
+        self.drag_expand_delay.set(delay);
+    }
+
+    pub fn set_item_height(&self, px: usize) {
+        self.item_height.set(px.max(1));
+        self.state.borrow_mut().offsets_stale = true;
+        self.update();
+    }
+
+    fn ensure_offsets(&self) {
+        let count = self.ctrl.count();
+        {
+            let state = self.state.borrow();
+            if !state.offsets_stale && state.offsets.len() == count {
+                return;
+            }
+        }
+
+        let item_height = self.item_height.get();
+        let offsets = RowOffsets::new(
+            (0..count).map(|index| self.ctrl.row_height(index).unwrap_or(item_height)),
+        );
+
+        let mut state = self.state.borrow_mut();
+        state.offsets = offsets;
+        state.offsets_stale = false;
+    }
+
+    // Resize notifications fire continuously while the window is dragged,
+    // they are coalesced into one paint per frame
+    pub fn update_size(&self, width: usize, height: usize) {
+        let changed = self.state.borrow_mut().set_size(width, height);
+        if changed {
+            self.request_update();
+        }
+    }
+
+    pub fn update_scroll(&self, offset_top: usize, offset_left: usize) {
+        let vertical = self.state.borrow_mut().set_scroll(offset_top, offset_left);
+        if vertical {
+            self.request_update();
+        }
+    }
+
+    // Paints on the next animation frame, any number of requests before it
+    // result in a single `update`
+    pub fn request_update(&self) {
+        if !self.paint.request() {
+            return;
+        }
+
+        let this = self.this.clone();
+        let paint = Closure::once_into_js(move || {
+            if let Some(this) = this.upgrade() {
+                if this.paint.take() {
+                    this.update();
+                }
+            }
+        });
+
+        let requested = web_sys::window()
+            .ok_or(TreeError::NoDocument)
+            .and_then(|window| Ok(window.request_animation_frame(paint.unchecked_ref())?));
+
+        // the paint stays pending until it runs, so without a frame it runs
+        // right away
+        if let Err(err) = requested {
+            tracing::warn!("Painting without an animation frame: {:?}", err);
+            self.update();
+        }
+    }
+
+    // Returns `false` if the node doesn't exist
+    pub fn scroll_to_key(&self, key: KeyType) -> bool {
+        let mut index = self.ctrl.index_of(key);
+        if index.is_none() && self.ctrl.reveal(key) {
+            index = self.ctrl.index_of(key);
+        }
+
+        let Some(index) = index else {
+            return false;
+        };
+
+        self.ensure_offsets();
+        let offset_top = self.state.borrow().offsets.offset(index);
+        self.container.set_scroll_top(offset_top as i32);
+
+        // painted right away, callers expect the row to be rendered
+        let mut state = self.state.borrow_mut();
+        let offset_left = state.offset_left;
+        state.set_scroll(offset_top, offset_left);
+        drop(state);
+
+        self.update();
+        true
+    }
+
+    // Scrolls by the smallest amount that makes the row fully visible
+    pub fn scroll_into_view(&self, key: KeyType) {
+        let Some(index) = self.ctrl.index_of(key) else {
+            return;
+        };
+
+        self.ensure_offsets();
+        let (offset, offset_left, height, top, item_height) = {
+            let state = self.state.borrow();
+            let top = state.offsets.offset(index);
+            let item_height = state.offsets.height(index);
+            (
+                state.offset,
+                state.offset_left,
+                state.size.1,
+                top,
+                item_height,
+            )
+        };
+
+        let offset_top = if top < offset {
+            top
+        } else if top + item_height > offset + height {
+            (top + item_height).saturating_sub(height)
+        } else {
+            return;
+        };
+
+        self.container.set_scroll_top(offset_top as i32);
+        self.update_scroll(offset_top, offset_left);
+    }
+
+    fn anchored_offset(&self, change: &TreeChange) -> Option<usize> {
+        let item_height = self.item_height.get();
+        let inserted_height = change
+            .inserted
+            .clone()
+            .map(|index| self.ctrl.row_height(index).unwrap_or(item_height))
+            .sum();
+
+        self.state.borrow().anchored_offset(change, inserted_height)
+    }
+
+    pub fn is_rendered(&self, key: KeyType) -> bool {
+        self.state.borrow().rendered.contains_key(&key)
+    }
+
+    pub fn focused(&self) -> Option<KeyType> {
+        self.state.borrow().focused
+    }
+
+    // Rows PageUp/PageDown move the focus by
+    pub fn page_size(&self) -> usize {
+        self.state.borrow().page_size(self.item_height.get())
+    }
+
+    // Focus is tracked separately from selection, so the focused row gets
+    // its own `tree-item-focused` class that can be styled as a ring
+    pub fn focus(&self, key: Option<KeyType>) {
+        self.state.borrow_mut().focused = key;
+        self.update();
+    }
+
+    // Keyboard navigation: focuses the row, scrolls to it and moves the DOM
+    // focus along if it is inside the tree, so Tab leaves from that row
+    fn focus_row(&self, key: KeyType) {
+        self.focus(Some(key));
+        self.scroll_into_view(key);
+
+        if !self.has_dom_focus() {
+            return;
+        }
+
+        let row = self
+            .state
+            .borrow()
+            .rendered
+            .get(&key)
+            .map(|row| row.container.clone());
+
+        if let Some(row) = row {
+            report(row.unchecked_into::<HtmlElement>().focus());
+        }
+    }
+
+    fn has_dom_focus(&self) -> bool {
+        let active = document()
+            .ok()
+            .and_then(|document| document.active_element());
+        active.is_some_and(|active| self.container.contains(Some(&active)))
+    }
+
+    // See `TreeState::row_tab_stop`
+    fn update_tab_stop(&self, row_stop: bool) {
+        let container_stop = !row_stop;
+        if self.container_tab_stop.get() == container_stop {
+            return;
+        }
+
+        match self
+            .container
+            .set_attribute("tabindex", row_tabindex(container_stop))
+        {
+            Ok(()) => self.container_tab_stop.set(container_stop),
+            Err(err) => report(Err(err)),
+        }
+    }
+
+    fn handle_transition_end(&self, ev: web_sys::Event) {
+        let Some(target) = event_target(&ev) else {
+            return;
+        };
+
+        if !target.class_list().contains("tree-item-leaving") {
+            return;
+        }
+
+        let Some(key) = row_key(&target) else {
+            return;
+        };
+
+        let mut state = self.state.borrow_mut();
+        if let Some(row) = state.leaving.finish(key) {
+            state.reclaim(row);
+        }
+    }
+
+    // Moving between the elements of one row fires `mouseover` as well, only
+    // moving to another row changes anything
+    fn handle_mouse_over(&self, ev: web_sys::MouseEvent) {
+        self.set_hovered(event_key(&ev));
+    }
+
+    fn handle_mouse_out(&self, ev: web_sys::MouseEvent) {
+        let inside = ev
+            .related_target()
+            .and_then(|target| target.dyn_into::<Node>().ok())
+            .is_some_and(|node| self.container.contains(Some(&node)));
+
+        if !inside {
+            self.set_hovered(None);
+        }
+    }
+
+    fn set_hovered(&self, key: Option<KeyType>) {
+        let mut state = self.state.borrow_mut();
+        let Some(previous) = state.set_hovered(key) else {
+            return;
+        };
+
+        for (key, hover) in [(previous, false), (key, true)] {
+            if let Some(row) = key.and_then(|key| state.rendered.get_mut(&key)) {
+                report(row.set_hover(hover));
+            }
+        }
+
+        drop(state);
+        self.ctrl.handle_hover(key);
+    }
+
+    fn handle_drag_start(&self, ev: web_sys::DragEvent) {
+        let Some(key) = event_key(&ev) else {
+            return;
+        };
+
+        // Firefox doesn't start a drag without data
+        if let Some(transfer) = ev.data_transfer() {
+            transfer.set_effect_allowed("move");
+            report(transfer.set_data("text/plain", &key.to_string()));
+        }
+
+        self.drag.replace(DragState {
+            dragged: Some(key),
+            hovered: None,
+        });
+    }
+
+    // Drags that didn't start in this tree are ignored
+    fn handle_drag_over(&self, ev: web_sys::DragEvent) {
+        let Some(dragged) = self.drag.borrow().dragged else {
+            return;
+        };
+
+        let target = self.drop_target_at(&ev);
+        let folder = target
+            .as_ref()
+            .filter(|(_, item)| item.expandable() && !item.expanded())
+            .map(|(target, _)| target.key);
+
+        if let Some(delay) = self.drag_expand_delay.get() {
+            let now = js_sys::Date::now();
+            let expand = self.drag.borrow_mut().hover(folder, now, delay as f64);
+            if let (true, Some(key)) = (expand, folder) {
+                self.ctrl.expand(key);
+            }
+        }
+
+        let Some((target, item)) = target else {
+            return self.hide_drop_indicator();
+        };
+
+        if !self.ctrl.can_drop(dragged, target) {
+            return self.hide_drop_indicator();
+        }
+
+        // accepts the drop
+        ev.prevent_default();
+        if let Some(transfer) = ev.data_transfer() {
+            transfer.set_drop_effect("move");
+        }
+
+        self.show_drop_indicator(target, &*item);
+    }
+
+    fn handle_drag_leave(&self, ev: web_sys::DragEvent) {
+        let inside = ev
+            .related_target()
+            .and_then(|target| target.dyn_into::<Node>().ok())
+            .is_some_and(|node| self.container.contains(Some(&node)));
+
+        if !inside {
+            self.drag.borrow_mut().hovered = None;
+            self.hide_drop_indicator();
+        }
+    }
+
+    fn handle_drop(&self, ev: web_sys::DragEvent) {
+        ev.prevent_default();
+        self.hide_drop_indicator();
+
+        let Some(dragged) = self.drag.take().dragged else {
+            return;
+        };
+
+        let Some((target, _)) = self.drop_target_at(&ev) else {
+            return;
+        };
+
+        if self.ctrl.can_drop(dragged, target) {
+            self.ctrl.handle_drop(dragged, target);
+        }
+    }
+
+    // Fires for cancelled drags as well
+    fn handle_drag_end(&self, _ev: web_sys::DragEvent) {
+        self.drag.take();
+        self.hide_drop_indicator();
+    }
+
+    fn drop_target_at(&self, ev: &web_sys::DragEvent) -> Option<(DropTarget, Rc<dyn TreeItem>)> {
+        let key = event_key(ev)?;
+        let index = self.ctrl.index_of(key)?;
+        let item = self.ctrl.item(index);
+
+        let rect = event_target(ev)?
+            .closest("[data-key]")
+            .ok()??
+            .get_bounding_client_rect();
+        let y = ev.client_y() as f64 - rect.top();
+        let position = drop_position(y, rect.height(), item.expandable());
+
+        Some((DropTarget { key, position }, item))
+    }
+
+    fn show_drop_indicator(&self, target: DropTarget, item: &dyn TreeItem) {
+        let Some(index) = self.ctrl.index_of(target.key) else {
+            return self.hide_drop_indicator();
+        };
+
+        let (top, height) = {
+            let state = self.state.borrow();
+            (state.offsets.offset(index), state.offsets.height(index))
+        };
+
+        let x = self.calc_shift(item);
+        let (class, style) = match target.position {
+            DropPosition::Before => ("tree-drop-before", format!("top: {}px; left: {}px", top, x)),
+            DropPosition::After => (
+                "tree-drop-after",
+                format!("top: {}px; left: {}px", top + height, x),
+            ),
+            DropPosition::Inside => (
+                "tree-drop-inside",
+                format!("top: {}px; left: {}px; height: {}px", top, x, height),
+            ),
+        };
+
+        let indicator = &self.drop_indicator;
+        report(
+            indicator
+                .set_attribute("class", &format!("tree-drop-indicator {}", class))
+                .and_then(|()| indicator.set_attribute("style", &style)),
+        );
+    }
+
+    fn hide_drop_indicator(&self) {
+        report(self.drop_indicator.set_attribute("style", "display: none"));
+    }
+
+    // Entering rows lose their class on the next frame, so the transition
+    // runs from the entering styles to the normal ones
+    fn schedule_animation_end(&self, entering: Vec<KeyType>, generation: usize) {
+        let Some(window) = web_sys::window() else {
+            return;
+        };
+
+        let this = self.this.clone();
+        let enter = Closure::once_into_js(move || {
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+
+            let mut state = this.state.borrow_mut();
+            for key in entering {
+                if let Some(row) = state.rendered.get_mut(&key) {
+                    report(row.set_entering(false));
+                }
+            }
+        });
+
+        report(
+            window
+                .request_animation_frame(enter.unchecked_ref())
+                .map(drop),
+        );
+
+        let this = self.this.clone();
+        let expire = Closure::once_into_js(move || {
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+
+            let mut state = this.state.borrow_mut();
+            for row in state.leaving.expire(generation) {
+                state.reclaim(row);
+            }
+        });
+
+        report(
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    expire.unchecked_ref(),
+                    LEAVE_TIMEOUT_MS,
+                )
+                .map(drop),
+        );
+    }
+
+    fn warm_pool(&self) {
+        // any item will do, pooled rows are fully updated on reuse
+        let blank = TreeNode::root();
+        let mut state = self.state.borrow_mut();
+        let limit = state.pool_limit();
+        for _ in 0..POOL_WARMUP {
+            let Ok(row) = self.create_row(&*blank, 0, 0) else {
+                break;
+            };
+
+            if !push_bounded(&mut state.pool, row, limit) {
+                break;
+            }
+        }
+    }
+
+    // Rows of the list, the sticky lane only holds copies of them
+    fn render_row(
+        &self,
+        item: &dyn TreeItem,
+        y: usize,
+        x: usize,
+    ) -> Result<RenderedItem, TreeError> {
+        let mut row = self.create_row(item, y, x)?;
+        row.bind_content(item);
+        Ok(row)
+    }
+
+    fn create_row(
+        &self,
+        item: &dyn TreeItem,
+        y: usize,
+        x: usize,
+    ) -> Result<RenderedItem, TreeError> {
+        let row = RenderedItem::render(
+            item,
+            y,
+            x,
+            self.arrow_position,
+            &self.theme,
+            self.row_renderer.as_ref(),
+        )?;
+        if self.drag_and_drop {
+            row.container.set_attribute("draggable", "true")?;
+        }
+
+        Ok(row)
+    }
+
+    // Rows of the lane are reused by position, the lane only ever holds a
+    // few of them
+    fn update_sticky_lane(
+        &self,
+        lane: &Element,
+        rows: &mut Vec<RenderedItem>,
+        ancestors: &[(usize, Rc<dyn TreeItem>)],
+        offsets: &RowOffsets,
+        offset: usize,
+    ) -> Result<(), TreeError> {
+        let pinned = pin_ancestors(ancestors, offsets, offset);
+        for (position, ((_, y), (_, item))) in pinned.into_iter().zip(ancestors).enumerate() {
+            let x = self.calc_shift(&**item);
+
+            if let Some(row) = rows.get_mut(position) {
+                row.update_item(&**item)?;
+                row.update_style(y, x)?;
+            } else {
+                let mut row = RenderedItem::render(
+                    &**item,
+                    0,
+                    x,
+                    self.arrow_position,
+                    &self.theme,
+                    self.row_renderer.as_ref(),
+                )?;
+                row.bind_content(&**item);
+                row.make_lane_copy()?;
+                row.update_style(y, x)?;
+                row.set_sticky(true)?;
+                lane.append_child(&row.container)?;
+                rows.push(row);
+            }
+        }
+
+        for row in rows.drain(ancestors.len()..) {
+            row.container.remove();
+        }
+
+        Ok(())
+    }
+
+    fn row_y(
+        &self,
+        index: usize,
+        item: &dyn TreeItem,
+        offset: usize,
+        offsets: &RowOffsets,
+    ) -> usize {
+        if item.is_group_header() {
+            let range = sticky_range(index, item.visible_children_len(), offsets);
+            offset.clamp(range.start, range.end)
+        } else {
+            offsets.offset(index)
+        }
+    }
+
+    // Refreshes a single row in place, rows that are scrolled out are
+    // picked up by the next full update anyway
+    pub fn update_item(&self, key: KeyType) {
+        self.update_items(&[key])
+    }
+
+    // Refreshes the given rows in a single pass. Keys that aren't rendered
+    // are skipped, their rows are built from the item once they scroll into
+    // view. A rendered key that is no longer in the visible list means the
+    // layout changed and needs a full update
+    pub fn update_items(&self, keys: &[KeyType]) {
+        if let Err(err) = self.try_update_items(keys) {
+            tracing::error!("Tree row update failed: {:?}", err);
+        }
+    }
+
+    fn try_update_items(&self, keys: &[KeyType]) -> Result<(), TreeError> {
+        // the pending paint refreshes these rows as well
+        if self.paint.is_pending() {
+            return Ok(());
+        }
+
+        let keys = self.state.borrow().rendered_keys(keys);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let Some(indices) = keys
+            .iter()
+            .map(|key| self.ctrl.index_of(*key))
+            .collect::<Option<Vec<_>>>()
+        else {
+            self.request_update();
+            return Ok(());
+        };
+
+        // a row that changed its height moves all the rows below it
+        self.ensure_offsets();
+        let item_height = self.item_height.get();
+        let mut resized = false;
+        for &index in &indices {
+            let height = self.ctrl.row_height(index).unwrap_or(item_height);
+            resized |= self.state.borrow_mut().offsets.set_height(index, height);
+        }
+
+        if resized {
+            self.request_update();
+            return Ok(());
+        }
+
+        // read before borrowing the state, see `FrameRows`
+        let rows = keys
+            .iter()
+            .zip(indices)
+            .map(|(key, index)| {
+                (
+                    *key,
+                    index,
+                    self.ctrl.item(index),
+                    self.ctrl.is_selected(*key),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        let offset = state.offset;
+        let checkbox_policy = self.checkbox_policy.get();
+
+        for (key, index, item, selected) in rows {
+            let y = self.row_y(index, &*item, offset, &state.offsets);
+            let Some(rendered) = state.touch(key) else {
+                continue;
+            };
+
+            rendered.update_style(y as isize, self.calc_shift(&*item))?;
+            rendered.update_item(&*item)?;
+            rendered.set_selected(selected)?;
+            rendered.update_checkbox(
+                checkbox_policy
+                    .shows_checkbox(&*item)
+                    .then(|| item.check_state()),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn calc_shift(&self, item: &dyn TreeItem) -> usize {
+        row_shift(item, self.arrow_position, self.indent.get())
+    }
+
+    pub fn set_indent_config(&self, indent: IndentConfig) {
+        self.indent.set(indent);
+        self.update();
+    }
+
+    // Moving the container to another parent resets its scroll position
+    // without firing a scroll event, so offsets are re-read from the element
+    fn revalidate_parent(&self) {
+        let parent = self.container.parent_element();
+        if *self.parent.borrow() == parent {
+            return;
+        }
+
+        self.parent.replace(parent);
+
+        let offset_top = self.container.scroll_top().max(0) as usize;
+        let offset_left = self.container.scroll_left().max(0) as usize;
+        self.state.borrow_mut().set_scroll(offset_top, offset_left);
+    }
+
+    pub fn update(&self) {
+        const LABEL: &str = "Tree::update";
+        web_sys::console::time_with_label(LABEL);
+        if let Err(err) = self.try_update() {
+            tracing::error!("Tree update failed: {:?}", err);
+        }
+
+        web_sys::console::time_end_with_label(LABEL);
+    }
+
+    // A failed paint leaves the rows it didn't reach as they were, the next
+    // update picks them up again
+    pub fn try_update(&self) -> Result<(), TreeError> {
+        self.paint.take();
+
+        self.revalidate_parent();
+        self.ensure_offsets();
+
+        let frame = FrameRows::read(&*self.ctrl, &self.state, self.sticky_lane.is_some());
+
+        let mut guard = self.state.borrow_mut();
+        // for splitting borrows
+        let state = &mut *guard;
+
+        // Update can happen because of:
+        //  scroll
+        //  resize
+        //  change items [diff can work]
+
+        let mut content_width = state.content_width;
+
+        let animate = self.animations && self.animate_next.replace(false);
+        let generation = state.leaving.next_generation();
+        let mut entering = vec![];
+
+        let offsets = &state.offsets;
+        let rendered = &mut state.rendered;
+        let focused = state.focused;
+        let hovered = state.hovered;
+        let offset = frame.offset;
+        let checkbox_policy = self.checkbox_policy.get();
+
+        let mut visited = BTreeSet::<KeyType>::new();
+
+        for (index, item, selected) in frame.rows {
+            let key = item.key();
+
+            visited.insert(key);
+
+            let sticky = item.is_group_header();
+            let y = self.row_y(index, &*item, offset, offsets);
+            content_width = content_width.max(row_width(
+                &*item,
+                self.arrow_position,
+                self.indent.get(),
+                self.char_width,
+            ));
+
+            let rendered_item = match rendered.entry(key) {
+                Entry::Occupied(entry) => {
+                    state.touched += 1;
+                    let rendered = entry.into_mut();
+                    rendered.update_style(y as isize, self.calc_shift(&*item))?;
+                    rendered.update_item(&*item)?;
+                    rendered
+                }
+                Entry::Vacant(entry) => {
+                    let mut rendered_item = if let Some(mut rendered) = state.pool.pop() {
+                        rendered.update_item(&*item)?;
+                        rendered.update_style(y as isize, self.calc_shift(&*item))?;
+                        rendered
+                    } else {
+                        self.render_row(&*item, y, self.calc_shift(&*item))?
+                    };
+
+                    if animate {
+                        rendered_item.set_entering(true)?;
+                        entering.push(key);
+                    }
+
+                    self.scroll.append_child(&rendered_item.container)?;
+                    entry.insert(rendered_item)
+                }
+            };
+
+            // pooled rows may still carry the state of a previous item
+            rendered_item.set_focused(focused == Some(key))?;
+            rendered_item.set_hover(hovered == Some(key))?;
+            rendered_item.set_selected(selected)?;
+            rendered_item.set_sticky(sticky)?;
+            rendered_item.update_checkbox(
+                checkbox_policy
+                    .shows_checkbox(&*item)
+                    .then(|| item.check_state()),
+            )?;
+        }
+
+        state.content_width = content_width;
+        let style = format!(
+            "height: {}px; min-width: {}px",
+            state.offsets.total(),
+            content_width
+        );
+        self.scroll.set_attribute("style", &style)?;
+
+        // a row scrolled out in the middle of an edit commits it. The rows
+        // are out of the list already, so they are all handled even if one
+        // of them fails
+        let mut lost_edit = None;
+        let mut lost_focus = false;
+        for (key, mut item) in state.take_unvisited(&visited) {
+            lost_focus |= item.has_dom_focus();
+            if item.is_editing() {
+                lost_edit = self.edit.take();
+                report(item.end_edit());
+            }
+
+            if animate {
+                // pooled once the transition ends
+                report(item.set_leaving(true));
+                state.leaving.push(key, item);
+                continue;
+            }
+
+            item.container.remove();
+            state.pool_row(item);
+        }
+
+        if animate {
+            self.schedule_animation_end(entering, generation);
+        }
+
+        if let Some(lane) = &self.sticky_lane {
+            self.update_sticky_lane(
+                lane,
+                &mut state.sticky_rows,
+                &frame.ancestors,
+                &state.offsets,
+                offset,
+            )?;
+        }
+
+        let row_stop = state.row_tab_stop();
+        drop(guard);
+
+        self.update_tab_stop(row_stop);
+        // a focused row scrolled out hands the focus back to the container
+        if lost_focus {
+            self.container.focus()?;
+        }
+
+        if let Some((key, input)) = lost_edit {
+            self.ctrl.handle_rename(key, input.value());
+        }
+
+        Ok(())
+    }
+}
+
+impl TreeSubscriber for TreeView {
+    fn update_all(&self) {
+        let mut state = self.state.borrow_mut();
+        state.offsets_stale = true;
+        state.content_width = 0;
+        drop(state);
+        self.animate_next.set(self.animations);
+        self.update_expand_toggle();
+        self.update_frozen();
+        self.update_loading();
+        self.request_update()
+    }
+
+    fn update_item(&self, key: usize) {
+        TreeView::update_item(self, key)
+    }
+
+    fn update_items(&self, keys: &[KeyType]) {
+        TreeView::update_items(self, keys)
+    }
+
+    // Only the scroll height changes when the rows are below the window, so
+    // there is nothing to animate. Changes above it scroll by the height
+    // they added or removed, the visible rows stay where they were
+    fn update_rows(&self, change: &TreeChange) {
+        if let Some(offset_top) = self.anchored_offset(change) {
+            let mut state = self.state.borrow_mut();
+            let offset_left = state.offset_left;
+            state.set_scroll(offset_top, offset_left);
+            state.offsets_stale = true;
+            drop(state);
+
+            self.update_expand_toggle();
+            self.update_loading();
+
+            // painted right away, the content has to grow before it can be
+            // scrolled further down
+            self.update();
+            self.container.set_scroll_top(offset_top as i32);
+            return;
+        }
+
+        if !self.state.borrow().is_below_window(change) {
+            return self.update_all();
+        }
+
+        self.state.borrow_mut().offsets_stale = true;
+        self.update_expand_toggle();
+        self.update_loading();
+        self.request_update()
+    }
+}
+
+thread_local! {
+    // Shared by all rows, the string is copied to the DOM right away
+    static STYLE_BUFFER: RefCell<String> = RefCell::new(String::with_capacity(64));
+}
+
+// ARIA attributes of a row that depend on the item, `aria-selected` follows
+// the selection instead
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AriaAttrs {
+    pub level: u16,
+    // Rows that can't expand have no `aria-expanded` at all
+    pub expanded: Option<bool>,
+    pub position: Option<(usize, usize)>,
+}
+
+impl AriaAttrs {
+    pub fn of(item: &dyn TreeItem) -> Self {
+        AriaAttrs {
+            level: item.depth(),
+            expanded: item.expandable().then(|| item.expanded()),
+            position: item.set_position(),
+        }
+    }
+}
+
+pub struct RenderedItem {
+    container: Element,
+    key: KeyType,
+    // Last written position, rows that stay in place don't touch the DOM
+    position: Option<(isize, usize)>,
+    hashes: ItemHashes,
+    aria: AriaAttrs,
+    expandable: bool,
+    expanded: bool,
+    focused: bool,
+    hover: bool,
+    selected: bool,
+    sticky: bool,
+    // A copy in the sticky lane, the row itself is in the list
+    lane_copy: bool,
+    check_state: Option<CheckState>,
+    load_progress: Option<f32>,
+    loading: bool,
+    error: bool,
+    arrow: Element,
+    checkbox: HtmlInputElement,
+    icon: Element,
+    text_span: Element,
+    text: Text,
+    title_ranges: Option<Vec<Range<usize>>>,
+    tooltip: Option<String>,
+    edit: Option<HtmlInputElement>,
+    spinner: Element,
+    progress: Element,
+    theme: Rc<TreeTheme>,
+    content: RowContent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentState {
+    // Warmed up rows, nothing rendered yet
+    Blank,
+    Bound,
+    // Pooled since the content was last updated
+    Recycled,
+}
+
+// The part of a row drawn by the `RowRenderer`
+struct RowContent {
+    renderer: Option<Rc<dyn RowRenderer>>,
+    state: ContentState,
+}
+
+impl RowContent {
+    fn new(renderer: Option<&Rc<dyn RowRenderer>>) -> Self {
+        Self {
+            renderer: renderer.cloned(),
+            state: ContentState::Blank,
+        }
+    }
+
+    // `changed` is set when the row shows another item or its detail hash
+    // moved since the last bind
+    fn bind(&mut self, container: &Element, item: &dyn TreeItem, changed: bool) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+
+        match self.state {
+            ContentState::Blank => renderer.render(container, item),
+            ContentState::Bound if !changed => return,
+            ContentState::Bound | ContentState::Recycled => renderer.update(container, item),
+        }
+
+        self.state = ContentState::Bound;
+    }
+
+    fn recycle(&mut self, container: &Element) {
+        if self.state != ContentState::Bound {
+            return;
+        }
+
+        if let Some(renderer) = &self.renderer {
+            renderer.recycle(container);
+        }
+
+        self.state = ContentState::Recycled;
+    }
+}
+
+impl RenderedItem {
+    pub fn render(
+        item: &dyn TreeItem,
+        y: usize,
+        x: usize,
+        arrow_position: ArrowPosition,
+        theme: &Rc<TreeTheme>,
+        row_renderer: Option<&Rc<dyn RowRenderer>>,
+    ) -> Result<Self, TreeError> {
+        let document = document()?;
+
+        let container = document.create_element("div")?;
+        container.set_attribute("class", &theme.item)?;
+        container.set_attribute("data-key", &item.key().to_string())?;
+
+        let expanded = item.expanded();
+        let expandable = item.expandable();
+
+        let arrow = document.create_element("span")?;
+        if !expandable {
+            arrow.set_attribute("style", "display: none")?;
+        }
+
+        theme.render_arrow(&arrow, expanded)?;
+
+        if arrow_position == ArrowPosition::Leading {
+            container.append_child(&arrow)?;
+        }
+
+        let checkbox: HtmlInputElement = document.create_element("input")?.unchecked_into();
+        checkbox.set_attribute("type", "checkbox")?;
+        checkbox.set_attribute("class", "tree-item-checkbox")?;
+        checkbox.set_attribute("style", "display: none")?;
+        container.append_child(&checkbox)?;
+
+        let icon = document.create_element("span")?;
+        Self::render_icon(&icon, displayed_icon(item).as_deref())?;
+        container.append_child(&icon)?;
+
+        let spinner = document.create_element("span")?;
+        spinner.set_attribute("class", "tree-item-spinner")?;
+        if !item.loading() {
+            spinner.set_attribute("style", "display: none")?;
+        }
+
+        container.append_child(&spinner)?;
+
+        let text_span = document.create_element("span")?;
+        let text = document.create_text_node("");
+        container.append_child(&text_span)?;
+
+        if arrow_position == ArrowPosition::Trailing {
+            container.append_child(&arrow)?;
+        }
+
+        let progress = document.create_element("div")?;
+        progress.set_attribute("class", "tree-item-progress")?;
+        progress.set_attribute("style", "display: none")?;
+        container.append_child(&progress)?;
+
+        let hashes = ItemHashes::of(item);
+        let mut this = Self {
+            container,
+            key: item.key(),
+            position: None,
+            expandable,
+            arrow,
+            expanded,
+            focused: false,
+            hover: false,
+            selected: false,
+            sticky: false,
+            lane_copy: false,
+            check_state: None,
+            load_progress: None,
+            loading: item.loading(),
+            error: false,
+            checkbox,
+            icon,
+            text_span,
+            text,
+            title_ranges: None,
+            tooltip: None,
+            edit: None,
+            spinner,
+            progress,
+            theme: theme.clone(),
+            content: RowContent::new(row_renderer),
+            hashes,
+            aria: AriaAttrs::default(),
+        };
+
+        this.container.set_attribute("role", "treeitem")?;
+        this.container
+            .set_attribute("tabindex", row_tabindex(false))?;
+        this.container.set_attribute("aria-selected", "false")?;
+        this.update_aria(AriaAttrs::of(item))?;
+        this.update_style(y as isize, x)?;
+        this.update_progress(item.load_progress())?;
+        this.set_error(item.error())?;
+        this.render_title(&item.title(), item.title_ranges(), true)?;
+        this.update_tooltip(item.tooltip().as_deref())?;
+
+        Ok(this)
+    }
+
+    fn update_tooltip(&mut self, tooltip: Option<&str>) -> Result<(), TreeError> {
+        if self.tooltip.as_deref() == tooltip {
+            return Ok(());
+        }
+
+        match tooltip {
+            Some(tooltip) => self.container.set_attribute("title", tooltip)?,
+            None => self.container.remove_attribute("title")?,
+        }
+
+        self.tooltip = tooltip.map(str::to_string);
+        Ok(())
+    }
+
+    // The element stays in place so the row layout doesn't shift when an
+    // icon appears later
+    fn render_icon(icon: &Element, class: Option<&str>) -> Result<(), TreeError> {
+        match class {
+            Some(class) => {
+                icon.set_attribute("class", class)?;
+                icon.remove_attribute("style")?;
+            }
+            None => {
+                icon.remove_attribute("class")?;
+                icon.set_attribute("style", "display: none")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn toggle_expanded(&mut self) -> Result<(), TreeError> {
+        self.theme.render_arrow(&self.arrow, !self.expanded)?;
+        self.expanded = !self.expanded;
+        Ok(())
+    }
+
+    pub fn set_focused(&mut self, focused: bool) -> Result<(), TreeError> {
+        if self.focused == focused {
+            return Ok(());
+        }
+
+        self.container
+            .set_attribute("tabindex", row_tabindex(focused))?;
+        toggle_classes(&self.container, &self.theme.focused, focused)?;
+        self.focused = focused;
+        Ok(())
+    }
+
+    pub fn set_hover(&mut self, hover: bool) -> Result<(), TreeError> {
+        if self.hover == hover {
+            return Ok(());
+        }
+
+        self.container
+            .class_list()
+            .toggle_with_force("tree-item-hover", hover)?;
+        self.hover = hover;
+        Ok(())
+    }
+
+    pub fn set_selected(&mut self, selected: bool) -> Result<(), TreeError> {
+        if self.selected == selected {
+            return Ok(());
+        }
+
+        toggle_classes(&self.container, &self.theme.selected, selected)?;
+        self.container
+            .set_attribute("aria-selected", if selected { "true" } else { "false" })?;
+        self.selected = selected;
+        Ok(())
+    }
+
+    // Pooled rows keep the attributes of their previous item, only the ones
+    // that differ are written
+    fn update_aria(&mut self, aria: AriaAttrs) -> Result<(), TreeError> {
+        let old = self.aria;
+        if old.level != aria.level {
+            self.container
+                .set_attribute("aria-level", &aria.level.to_string())?;
+        }
+
+        if old.expanded != aria.expanded {
+            match aria.expanded {
+                Some(expanded) => self
+                    .container
+                    .set_attribute("aria-expanded", if expanded { "true" } else { "false" })?,
+                None => self.container.remove_attribute("aria-expanded")?,
+            }
+        }
+
+        if old.position != aria.position {
+            match aria.position {
+                Some((position, size)) => {
+                    self.container
+                        .set_attribute("aria-posinset", &position.to_string())?;
+                    self.container
+                        .set_attribute("aria-setsize", &size.to_string())?;
+                }
+                None => {
+                    self.container.remove_attribute("aria-posinset")?;
+                    self.container.remove_attribute("aria-setsize")?;
+                }
+            }
+        }
+
+        self.aria = aria;
+        Ok(())
+    }
+
+    pub fn set_entering(&mut self, entering: bool) -> Result<(), TreeError> {
+        self.container
+            .class_list()
+            .toggle_with_force("tree-item-entering", entering)?;
+        Ok(())
+    }
+
+    pub fn set_leaving(&mut self, leaving: bool) -> Result<(), TreeError> {
+        self.container
+            .class_list()
+            .toggle_with_force("tree-item-leaving", leaving)?;
+        Ok(())
+    }
+
+    fn set_error(&mut self, error: bool) -> Result<(), TreeError> {
+        if self.error == error {
+            return Ok(());
+        }
+
+        self.container
+            .class_list()
+            .toggle_with_force("tree-item-error", error)?;
+        self.error = error;
+        Ok(())
+    }
+
+    // Events and assistive tech only see the row in the list
+    fn make_lane_copy(&mut self) -> Result<(), TreeError> {
+        self.container.remove_attribute("data-key")?;
+        self.container.remove_attribute("role")?;
+        self.lane_copy = true;
+        Ok(())
+    }
+
+    pub fn set_sticky(&mut self, sticky: bool) -> Result<(), TreeError> {
+        if self.sticky == sticky {
+            return Ok(());
+        }
+
+        self.container
+            .class_list()
+            .toggle_with_force("tree-item-sticky", sticky)?;
+        self.sticky = sticky;
+        Ok(())
+    }
+
+    // `None` hides the checkbox
+    pub fn update_checkbox(&mut self, check_state: Option<CheckState>) -> Result<(), TreeError> {
+        if self.check_state == check_state {
+            return Ok(());
+        }
+
+        match check_state {
+            Some(_) => {
+                if self.check_state.is_none() {
+                    self.checkbox.remove_attribute("style")?;
+                }
+            }
+            None => {
+                self.checkbox.set_attribute("style", "display: none")?;
+            }
+        }
+
+        self.check_state = check_state;
+        self.reset_checkbox();
+        Ok(())
+    }
+
+    fn reset_checkbox(&self) {
+        if let Some(state) = self.check_state {
+            self.checkbox.set_checked(state == CheckState::Checked);
+            self.checkbox
+                .set_indeterminate(state == CheckState::Indeterminate);
+        }
+    }
+
+    pub fn update_progress(&mut self, load_progress: Option<f32>) -> Result<(), TreeError> {
+        if self.load_progress == load_progress {
+            return Ok(());
+        }
+
+        let style = progress_style(load_progress);
+        self.progress
+            .set_attribute("style", style.as_deref().unwrap_or("display: none"))?;
+
+        self.load_progress = load_progress;
+        Ok(())
+    }
+
+    // Highlighted titles are built from `<mark>` spans, plain ones reuse a
+    // single text node
+    fn render_title(
+        &mut self,
+        title: &str,
+        ranges: Option<Vec<Range<usize>>>,
+        fresh: bool,
+    ) -> Result<(), TreeError> {
+        let Some(spans) = ranges.as_deref().map(|r| title_spans(title, r)) else {
+            self.text.set_data(title);
+            if fresh || self.title_ranges.is_some() {
+                self.text_span.set_text_content(None);
+                self.text_span.append_child(&self.text)?;
+            }
+
+            self.title_ranges = None;
+            return Ok(());
+        };
+
+        let document = document()?;
+        self.text_span.set_text_content(None);
+        for (text, highlighted) in spans {
+            if highlighted {
+                let mark = document.create_element("mark")?;
+                mark.set_text_content(Some(text));
+                self.text_span.append_child(&mark)?;
+            } else {
+                let text = document.create_text_node(text);
+                self.text_span.append_child(&text)?;
+            }
+        }
+
+        self.title_ranges = ranges;
+        Ok(())
+    }
+
+    // A pooled row keeps the spans of its last item, so ranges are compared
+    // even when the title is the same
+    fn title_stale(&self, title: HashType, ranges: &Option<Vec<Range<usize>>>) -> bool {
+        title != self.hashes.title || *ranges != self.title_ranges
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.edit.is_some()
+    }
+
+    // The title stays hidden and isn't updated until `end_edit`
+    pub fn begin_edit(&mut self, input: &HtmlInputElement) -> Result<(), TreeError> {
+        self.end_edit()?;
+        self.text_span.set_attribute("style", "display: none")?;
+        self.container
+            .insert_before(input, self.text_span.next_sibling().as_ref())?;
+
+        self.edit = Some(input.clone());
+        Ok(())
+    }
+
+    pub fn end_edit(&mut self) -> Result<(), TreeError> {
+        if let Some(input) = self.edit.take() {
+            input.remove();
+            self.text_span.remove_attribute("style")?;
+        }
+
+        Ok(())
+    }
+
+    // Negative offsets only happen for rows pushed out of the sticky lane
+    pub fn update_style(&mut self, y: isize, x: usize) -> Result<(), TreeError> {
+        if self.position == Some((y, x)) {
+            return Ok(());
+        }
+
+        STYLE_BUFFER.with(|style| {
+            let mut style = style.borrow_mut();
+            style.clear();
+            // writing to a `String` can't fail
+            let _ = write!(style, "top: {}px; padding-left: {}px", y, x);
+            self.container.set_attribute("style", &style)
+        })?;
+
+        self.position = Some((y, x));
+        Ok(())
+    }
+
+    // The custom content is only rendered once the row shows an item,
+    // warmed up rows skip it
+    fn bind_content(&mut self, item: &dyn TreeItem) {
+        self.content.bind(&self.container, item, true);
+    }
+
+    fn has_dom_focus(&self) -> bool {
+        self.focused
+            && document()
+                .ok()
+                .and_then(|document| document.active_element())
+                .is_some_and(|active| active == self.container)
+    }
+
+    fn recycle(&mut self) {
+        self.content.recycle(&self.container);
+    }
+
+    pub fn update_item(&mut self, item: &dyn TreeItem) -> Result<(), TreeError> {
+        if self.expandable != item.expandable() {
+            if item.expandable() {
+                self.arrow.remove_attribute("style")?;
+            } else {
+                self.arrow.set_attribute("style", "display: none")?;
+            }
+
+            self.expandable = item.expandable();
+        }
+
+        // folders can have a different icon while expanded, which isn't
+        // part of the data hashes
+        let expanded_changed = self.expanded != item.expanded();
+        if expanded_changed {
+            self.toggle_expanded()?;
+        }
+
+        if self.loading != item.loading() {
+            if item.loading() {
+                self.spinner.remove_attribute("style")?;
+            } else {
+                self.spinner.set_attribute("style", "display: none")?;
+            }
+
+            self.loading = item.loading();
+        }
+
+        self.update_progress(item.load_progress())?;
+        self.set_error(item.error())?;
+        self.update_aria(AriaAttrs::of(item))?;
+
+        // only changes when the row is reused from the pool
+        let rebound = self.key != item.key();
+        if rebound {
+            if !self.lane_copy {
+                self.container
+                    .set_attribute("data-key", &item.key().to_string())?;
+            }
+
+            self.key = item.key();
+        }
+
+        // ranges change with the query while the title stays the same, so
+        // they are checked before the hashes
+        let mut hashes = ItemHashes::of(item);
+        let title_ranges = item.title_ranges();
+        if self.is_editing() {
+            // rendered once the edit is over
+            hashes.title = self.hashes.title;
+        } else if self.title_stale(hashes.title, &title_ranges) {
+            self.render_title(&item.title(), title_ranges, false)?;
+        }
+
+        if expanded_changed || hashes.icon != self.hashes.icon {
+            Self::render_icon(&self.icon, displayed_icon(item).as_deref())?;
+        }
+
+        self.content.bind(
+            &self.container,
+            item,
+            rebound || hashes.detail != self.hashes.detail,
+        );
+
+        if hashes == self.hashes {
+            return Ok(());
+        }
+
+        // no separate hash, any change of the data may change it
+        if hashes.hash != self.hashes.hash {
+            self.update_tooltip(item.tooltip().as_deref())?;
+        }
+
+        self.hashes = hashes;
+        Ok(())
+    }
+}
 
 #[wasm_bindgen]
 extern "C" {
     fn observe(element: &Node, callback: &Function) -> JsValue;
     fn unobserve(ro: &JsValue);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::DynamicTree;
+    use crate::node::tests::{expanded_root, node, node_with_flags, TestData};
+    use crate::node::{TreeFlags, TreeNode};
+
+    #[test]
+    fn horizontal_scroll_keeps_visible_range() {
+        let mut state = TreeState {
+            count: 1000,
+            size: (300, 240),
+            offsets: RowOffsets::uniform(1000, 24),
+            ..Default::default()
+        };
+
+        assert!(state.set_scroll(480, 0));
+        let range = state.visible_range();
+        assert_eq!(range, 20..32);
+
+        assert!(!state.set_scroll(480, 120));
+        assert_eq!(state.visible_range(), range);
+        assert_eq!(state.offset_left, 120);
+    }
+
+    #[test]
+    fn escape_collapses_then_focuses_parent() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root =
+            expanded_root().build(|root| {
+                vec![node_with_flags(root, 1, "1", folder)
+                    .build(|parent| vec![node(parent, 11, "1.1")])]
+            });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let actions = DEFAULT_ESCAPE_ACTIONS;
+
+        assert_eq!(
+            apply_escape(&*tree, actions, Some(1)),
+            Some((EscapeAction::Collapse, Some(1)))
+        );
+        assert_eq!(tree.count(), 1);
+
+        tree.expand(1);
+        assert_eq!(
+            apply_escape(&*tree, actions, Some(11)),
+            Some((EscapeAction::FocusParent, Some(1)))
+        );
+
+        assert_eq!(
+            apply_escape(&*tree, &[EscapeAction::ClearFilter], Some(1)),
+            None
+        );
+        assert_eq!(apply_escape(&*tree, actions, None), None);
+    }
+
+    #[test]
+    fn expand_toggle() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![node_with_flags(parent, 11, "1.1", folder)
+                        .build(|parent| vec![node(parent, 111, "1.1.1")])]
+                }),
+                node_with_flags(root, 2, "2", TreeFlags::EXPANDABLE),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert!(tree.all_expanded());
+
+        toggle_expand_all(&*tree);
+        assert!(!tree.all_expanded());
+        assert_eq!(tree.count(), 2);
+
+        // the unloaded folder stays collapsed
+        toggle_expand_all(&*tree);
+        assert!(tree.all_expanded());
+        assert_eq!(tree.count(), 4);
+    }
+
+    #[test]
+    fn checkbox_policy() {
+        let root = expanded_root();
+        let folder = node_with_flags(&root, 1, "1", TreeFlags::EXPANDABLE);
+        let leaf = node(&root, 2, "2");
+
+        let policy = CheckboxPolicy::LeavesOnly;
+        assert!(!policy.shows_checkbox(&*folder));
+        assert!(policy.shows_checkbox(&*leaf));
+
+        assert!(CheckboxPolicy::All.shows_checkbox(&*folder));
+        assert!(!CheckboxPolicy::None.shows_checkbox(&*leaf));
+    }
+
+    #[test]
+    fn sticky_header_range() {
+        let offsets = RowOffsets::uniform(20, 24);
+        let range = sticky_range(10, 5, &offsets);
+        assert_eq!(range, 240..360);
+
+        // before the group the header stays in place, inside it is pinned
+        // to the offset and at the end it gets pushed out
+        assert_eq!(100usize.clamp(range.start, range.end), 240);
+        assert_eq!(300usize.clamp(range.start, range.end), 300);
+        assert_eq!(400usize.clamp(range.start, range.end), 360);
+
+        assert_eq!(sticky_range(0, 0, &offsets), 0..0);
+    }
+
+    #[test]
+    fn progress_bar() {
+        assert_eq!(progress_style(None), None);
+        assert_eq!(progress_style(Some(0.0)).as_deref(), Some("width: 0%"));
+        assert_eq!(progress_style(Some(0.25)).as_deref(), Some("width: 25%"));
+        assert_eq!(progress_style(Some(1.0)), None);
+
+        let tree = DynamicTree::new(
+            expanded_root().build(|root| vec![node(root, 1, "1")]),
+            Default::default(),
+        );
+        tree.set_sync_updates(true);
+        tree.set_load_progress(1, Some(0.5));
+        assert_eq!(tree.item(0).load_progress(), Some(0.5));
+
+        tree.set_load_progress(1, None);
+        assert_eq!(tree.item(0).load_progress(), None);
+    }
+
+    #[test]
+    fn update_item_skips_rows_out_of_view() {
+        let mut state = TreeState::default();
+        assert!(state.touch(1).is_none());
+        assert_eq!(state.touched, 0);
+
+        for key in [1, 2] {
+            state.rendered.insert(key, detached_row(key, None));
+        }
+
+        // neither unknown nor scrolled out keys lead to any update
+        assert!(state.rendered_keys(&[3]).is_empty());
+        assert_eq!(state.rendered_keys(&[3, 1]), [1]);
+
+        for key in state.rendered_keys(&[1]) {
+            state.touch(key);
+        }
+
+        assert_eq!(state.touched, 1);
+    }
+
+    #[test]
+    fn copy_selected_titles() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "src", folder)
+                    .build(|parent| vec![node(parent, 11, "lib.rs"), node(parent, 12, "view.rs")]),
+                node(root, 2, "Cargo.toml"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        tree.set_sync_updates(true);
+        assert_eq!(copy_text(&*tree, None), None);
+        assert_eq!(copy_text(&*tree, Some(12)).as_deref(), Some("\tview.rs"));
+
+        tree.toggle_selected(2);
+        tree.toggle_selected(11);
+        tree.toggle_selected(1);
+        assert_eq!(
+            copy_text(&*tree, Some(12)).as_deref(),
+            Some("src\n\tlib.rs\nCargo.toml")
+        );
+    }
+
+    #[test]
+    fn trailing_arrow_shift() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(root, 1, "src", folder)
+                .build(|parent| vec![node(parent, 11, "lib.rs")])]
+        });
+
+        let folder = root.get(1).unwrap();
+        let file = folder.get(11).unwrap();
+
+        let indent = IndentConfig::default();
+        assert_eq!(row_shift(&*folder, ArrowPosition::Leading, indent), 10);
+        assert_eq!(row_shift(&*file, ArrowPosition::Leading, indent), 42);
+
+        // trailing arrows leave folders and files at the same indentation
+        assert_eq!(row_shift(&*folder, ArrowPosition::Trailing, indent), 26);
+        assert_eq!(row_shift(&*file, ArrowPosition::Trailing, indent), 42);
+    }
+
+    #[test]
+    fn custom_indent() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(root, 1, "src", folder)
+                .build(|parent| vec![node(parent, 11, "lib.rs")])]
+        });
+
+        let indent = IndentConfig {
+            per_level: 20,
+            base: 4,
+            arrow_width: 12,
+        };
+
+        let folder = root.get(1).unwrap();
+        let file = folder.get(11).unwrap();
+        assert_eq!(row_shift(&*folder, ArrowPosition::Leading, indent), 12);
+        assert_eq!(row_shift(&*file, ArrowPosition::Leading, indent), 44);
+    }
+
+    #[test]
+    fn page_keys() {
+        let root = expanded_root().build(|root| (1..=10).map(|key| node(root, key, "")).collect());
+        let tree = DynamicTree::new(root, Default::default());
+        let page = |key, focused| apply_page_key(&*tree, key, focused, 4);
+
+        assert_eq!(page("Home", Some(7)), Some(1));
+        assert_eq!(page("End", None), Some(10));
+        assert_eq!(page("PageDown", None), Some(1));
+        assert_eq!(page("PageDown", Some(1)), Some(5));
+        // clamped to the last row
+        assert_eq!(page("PageDown", Some(8)), Some(10));
+        assert_eq!(page("PageUp", Some(7)), Some(3));
+        assert_eq!(page("PageUp", Some(3)), Some(1));
+        assert_eq!(page("Tab", Some(3)), None);
+
+        let empty = DynamicTree::new(expanded_root(), Default::default());
+        assert_eq!(apply_page_key(&*empty, "End", None, 4), None);
+    }
+
+    #[test]
+    fn page_size_from_viewport() {
+        let mut state = TreeState::default();
+        assert_eq!(state.page_size(24), 1);
+
+        state.set_size(300, 240);
+        assert_eq!(state.page_size(24), 10);
+        assert_eq!(state.page_size(25), 9);
+    }
+
+    #[test]
+    fn typeahead_prefix() {
+        let root = expanded_root().build(|root| {
+            vec![
+                node(root, 1, "apple"),
+                node(root, 2, "Banana"),
+                node(root, 3, "avocado"),
+                node(root, 4, "blueberry"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let find = |prefix, focused| typeahead_match(&*tree, prefix, focused);
+
+        assert_eq!(find("b", None), Some(2));
+        assert_eq!(find("B", Some(2)), Some(4));
+        // wraps around past the last row
+        assert_eq!(find("b", Some(4)), Some(2));
+        // a longer prefix keeps the focused row while it matches
+        assert_eq!(find("av", Some(3)), Some(3));
+        assert_eq!(find("bl", Some(2)), Some(4));
+        // the same letter again cycles
+        assert_eq!(find("aa", Some(1)), Some(3));
+        assert_eq!(find("x", Some(1)), None);
+        assert_eq!(find("", Some(1)), None);
+    }
+
+    #[test]
+    fn typeahead_timeout() {
+        let mut typeahead = Typeahead::default();
+        assert_eq!(typeahead.push('a', 1000.0), "a");
+        assert_eq!(typeahead.push('v', 1500.0), "av");
+        assert!(!typeahead.is_empty(2000.0));
+        assert!(typeahead.is_empty(2300.0));
+        assert_eq!(typeahead.push('b', 2300.0), "b");
+    }
+
+    #[test]
+    fn one_tab_stop_after_scrolling() {
+        let mut state = TreeState {
+            focused: Some(2),
+            max_pool_size: Some(4),
+            ..Default::default()
+        };
+
+        for key in [1, 2, 3] {
+            let mut row = detached_row(key, None);
+            row.focused = state.focused == Some(key);
+            state.rendered.insert(key, row);
+        }
+
+        let focused_rows =
+            |state: &TreeState| state.rendered.values().filter(|row| row.focused).count();
+        assert_eq!(focused_rows(&state), 1);
+        assert!(state.row_tab_stop());
+
+        // scrolled down past the focused row
+        let mut gone = state.take_unvisited(&BTreeSet::from([3, 4]));
+        gone.sort_by_key(|(key, _)| *key);
+        assert_eq!(gone.iter().map(|(key, _)| *key).collect::<Vec<_>>(), [1, 2]);
+        for (_, row) in gone {
+            state.pool_row(row);
+        }
+
+        // the container is the tab stop now
+        assert_eq!(focused_rows(&state), 0);
+        assert!(!state.row_tab_stop());
+
+        state.focused = Some(3);
+        assert!(state.row_tab_stop());
+    }
+
+    #[test]
+    fn arrow_keys() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| vec![node(parent, 11, "1.1")]),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+
+        assert_eq!(apply_arrow_key(&*tree, "ArrowDown", None), Some(1));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowDown", Some(1)), Some(2));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowDown", Some(2)), Some(2));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowUp", Some(2)), Some(1));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowUp", Some(1)), Some(1));
+
+        // right expands first, then moves into the children
+        assert_eq!(apply_arrow_key(&*tree, "ArrowRight", Some(1)), Some(1));
+        assert_eq!(tree.count(), 3);
+        assert_eq!(apply_arrow_key(&*tree, "ArrowRight", Some(1)), Some(11));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowRight", Some(2)), Some(2));
+
+        // left goes to the parent, then collapses it
+        assert_eq!(apply_arrow_key(&*tree, "ArrowLeft", Some(11)), Some(1));
+        assert_eq!(apply_arrow_key(&*tree, "ArrowLeft", Some(1)), Some(1));
+        assert_eq!(tree.count(), 2);
+
+        assert_eq!(apply_arrow_key(&*tree, "ArrowRight", None), None);
+        assert_eq!(apply_arrow_key(&*tree, "Tab", Some(1)), None);
+    }
+
+    #[test]
+    fn highlighted_title_spans() {
+        assert_eq!(title_spans("main.rs", &[]), [("main.rs", false)]);
+        assert_eq!(
+            title_spans("main.rs", &[0..2, 5..7]),
+            [("ma", true), ("in.", false), ("rs", true)]
+        );
+
+        // overlapping and unsorted ranges are merged
+        assert_eq!(
+            title_spans("main.rs", &[3..5, 1..4]),
+            [("m", false), ("ain.", true), ("rs", false)]
+        );
+
+        // out of bounds and mid-char ranges are dropped
+        assert_eq!(title_spans("ёж", &[1..2, 2..10]), [("ёж", false)]);
+    }
+
+    #[test]
+    fn drop_position_in_row() {
+        use DropPosition::*;
+
+        let leaf = [0.0, 11.0, 12.0, 23.0].map(|y| drop_position(y, 24.0, false));
+        assert_eq!(leaf, [Before, Before, After, After]);
+
+        let folder = [0.0, 5.0, 6.0, 17.0, 18.0, 23.0].map(|y| drop_position(y, 24.0, true));
+        assert_eq!(folder, [Before, Before, Inside, Inside, After, After]);
+
+        assert_eq!(drop_position(10.0, 0.0, true), Before);
+    }
+
+    #[test]
+    fn hover_expands_once() {
+        let mut drag = DragState::default();
+        let mut hover = |key, now| drag.hover(key, now, 600.0);
+
+        assert!(!hover(Some(1), 0.0));
+        assert!(!hover(Some(1), 599.0));
+        assert!(hover(Some(1), 600.0));
+        assert!(!hover(Some(1), 2000.0));
+
+        // another row starts over
+        assert!(!hover(Some(2), 2100.0));
+        assert!(!hover(Some(1), 2200.0));
+        assert!(!hover(Some(1), 2700.0));
+        assert!(hover(Some(1), 2800.0));
+
+        // so does leaving the rows
+        assert!(!hover(Some(3), 3000.0));
+        assert!(!hover(None, 3500.0));
+        assert!(!hover(Some(3), 3700.0));
+        assert!(!hover(Some(3), 4200.0));
+    }
+
+    #[test]
+    fn hover_follows_key() {
+        let mut state = TreeState {
+            max_pool_size: Some(4),
+            ..Default::default()
+        };
+
+        assert_eq!(state.set_hovered(Some(3)), Some(None));
+        assert_eq!(state.set_hovered(Some(3)), None);
+        assert_eq!(state.set_hovered(Some(4)), Some(Some(3)));
+        assert_eq!(state.set_hovered(None), Some(Some(4)));
+        assert_eq!(state.set_hovered(None), None);
+
+        // pooled rows keep their key until they're bound to another item,
+        // the hover class is set from that key then
+        let recorder = Rc::new(RecordingRenderer::default());
+        let renderer: Rc<dyn RowRenderer> = recorder.clone();
+        let mut row = detached_row(3, Some(&renderer));
+        row.bind_content(&*node(&expanded_root(), 3, "3"));
+        state.pool_row(row);
+        assert_eq!(state.pool.last().unwrap().key, 3);
+
+        let mut row = state.pool.pop().unwrap();
+        row.bind_content(&*node(&expanded_root(), 7, "7"));
+        assert_eq!(
+            recorder.take(),
+            [("render", Some(3)), ("recycle", None), ("update", Some(7))]
+        );
+    }
+
+    #[test]
+    fn changes_below_window() {
+        let state = TreeState {
+            count: 100,
+            size: (300, 240),
+            offsets: RowOffsets::new((0..100).map(|_| 24)),
+            ..Default::default()
+        };
+
+        let change = |start: usize| TreeChange {
+            removed: start..start,
+            inserted: start..start + 5,
+        };
+
+        assert_eq!(state.visible_range(), 0..12);
+        assert!(!state.is_below_window(&change(3)));
+        assert!(!state.is_below_window(&change(11)));
+        assert!(state.is_below_window(&change(12)));
+    }
+
+    #[derive(Default)]
+    struct Changes(RefCell<Vec<TreeChange>>);
+
+    impl TreeSubscriber for Changes {
+        fn update_all(&self) {}
+
+        fn update_item(&self, _key: usize) {}
+
+        fn update_rows(&self, change: &TreeChange) {
+            self.0.borrow_mut().push(change.clone())
+        }
+    }
+
+    #[test]
+    fn expand_above_keeps_scroll_anchor() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::READY;
+        let root = expanded_root().build(|root| {
+            (0..1000)
+                .map(|key| {
+                    if key != 2 {
+                        return node(root, key, &key.to_string());
+                    }
+
+                    node_with_flags(root, key, "2", folder).build(|parent| {
+                        (0..10)
+                            .map(|child| node(parent, 10_000 + child, "child"))
+                            .collect()
+                    })
+                })
+                .collect()
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let changes = Rc::new(Changes::default());
+        tree.add_subscriber(changes.clone());
+
+        let mut state = TreeState {
+            count: 1000,
+            size: (300, 240),
+            offsets: RowOffsets::uniform(1000, 24),
+            ..Default::default()
+        };
+        state.set_scroll(500 * 24, 0);
+
+        let first_visible =
+            |state: &TreeState| tree.item(state.offsets.index_at(state.offset)).key();
+        assert_eq!(first_visible(&state), 500);
+
+        for expected in [1010, 1000] {
+            tree.expand(2);
+            let change = changes.0.borrow_mut().pop().unwrap();
+            let offset = state
+                .anchored_offset(&change, change.inserted.len() * 24)
+                .unwrap();
+
+            state.set_scroll(offset, 0);
+            state.offsets = RowOffsets::uniform(expected, 24);
+            assert_eq!(first_visible(&state), 500);
+        }
+
+        // rows in the window aren't moved
+        state.set_scroll(0, 0);
+        tree.expand(2);
+        let change = changes.0.borrow_mut().pop().unwrap();
+        assert_eq!(state.anchored_offset(&change, 240), None);
+    }
+
+    #[test]
+    fn visible_range_with_mixed_heights() {
+        // every tenth row is a tall one
+        let heights = (0..100).map(|index| if index % 10 == 0 { 96 } else { 24 });
+        let mut state = TreeState {
+            count: 100,
+            size: (300, 240),
+            offsets: RowOffsets::new(heights),
+            ..Default::default()
+        };
+
+        assert_eq!(state.visible_range(), 0..9);
+
+        // rows 0..10 take 96 + 9 * 24 = 312px, the tall row 10 ends at 408
+        state.set_scroll(420, 0);
+        let range = state.visible_range();
+        assert_eq!(range.start, 11);
+        assert_eq!(state.offsets.offset(range.start), 408);
+        assert!(state.offsets.offset(range.end) >= 420 + 240);
+    }
+
+    #[test]
+    fn row_heights_from_data() {
+        let root = expanded_root();
+        let tall = TreeNode::new(
+            &root,
+            Box::new(TestData {
+                height: Some(48),
+                ..TestData::new(2, "2")
+            }),
+        );
+
+        let root = root.build(|root| vec![node(root, 1, "1"), tall]);
+        let tree = DynamicTree::new(root, Default::default());
+
+        assert_eq!(tree.row_height(0), None);
+        assert_eq!(tree.row_height(1), Some(48));
+        assert_eq!(tree.row_height(2), None);
+    }
+
+    #[test]
+    fn deep_tree_width() {
+        let root = expanded_root();
+        let mut parent = root.clone();
+        for key in 1..=30 {
+            let flags = if key < 30 {
+                TreeFlags::EXPANDABLE | TreeFlags::EXPANDED
+            } else {
+                TreeFlags::empty()
+            };
+
+            let child = node_with_flags(&parent, key, "node", flags);
+            parent.insert(vec![child.clone()]);
+            parent = child;
+        }
+
+        let tree = DynamicTree::new(root, Default::default());
+        assert_eq!(tree.count(), 30);
+
+        let indent = IndentConfig::default();
+        let width = (0..tree.count())
+            .map(|index| row_width(&*tree.item(index), ArrowPosition::Leading, indent, 8))
+            .max()
+            .unwrap();
+
+        // the deepest leaf: 30 levels of indent, the icon and four glyphs
+        assert_eq!(width, 30 * 16 + 10 + 16 + 4 * 8);
+    }
+
+    #[test]
+    fn pinned_ancestors() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", folder).build(|parent| {
+                            (111..115).map(|key| node(parent, key, "leaf")).collect()
+                        }),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        // 0: 1, 1: 1.1, 2..6: leaves, 6: 1.2, 7: 2
+        let tree = DynamicTree::new(root, Default::default());
+        let offsets = RowOffsets::uniform(tree.count(), 24);
+
+        assert!(sticky_ancestors(&*tree, &offsets, 0).is_empty());
+        assert_eq!(sticky_ancestors(&*tree, &offsets, 24), [(0, 0)]);
+        assert_eq!(sticky_ancestors(&*tree, &offsets, 48), [(0, 0), (1, 24)]);
+
+        // the last leaf ends at 144, so 1.1 is pushed up by 4px
+        assert_eq!(sticky_ancestors(&*tree, &offsets, 100), [(0, 0), (1, 20)]);
+
+        // past 1.1 only its parent stays, which is pushed out by 1.2 in turn
+        assert_eq!(sticky_ancestors(&*tree, &offsets, 144), [(0, 0)]);
+        assert_eq!(sticky_ancestors(&*tree, &offsets, 160), [(0, -16)]);
+        assert!(sticky_ancestors(&*tree, &offsets, 168).is_empty());
+    }
+
+    #[test]
+    fn leaving_rows_return_to_pool() {
+        let mut leaving = LeavingRows::default();
+        let mut pool = vec![];
+
+        let first = leaving.next_generation();
+        leaving.push(1, "1");
+        leaving.push(2, "2");
+
+        let second = leaving.next_generation();
+        leaving.push(3, "3");
+
+        // 1 finishes its transition, 2 never had one
+        pool.extend(leaving.finish(1));
+        assert_eq!(leaving.finish(1), None);
+
+        pool.extend(leaving.expire(first));
+        assert_eq!(pool, ["1", "2"]);
+        assert_eq!(leaving.len(), 1);
+
+        pool.extend(leaving.expire(second));
+        assert!(leaving.is_empty());
+        assert_eq!(pool, ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn paints_once_per_frame() {
+        let batch = FrameBatch::default();
+        let mut frames = 0;
+        let mut paints = 0;
+
+        for _ in 0..10 {
+            if batch.request() {
+                frames += 1;
+            }
+        }
+
+        // the frame callback
+        if batch.take() {
+            paints += 1;
+        }
+
+        assert_eq!((frames, paints), (1, 1));
+
+        // requests after the paint get their own frame
+        assert!(batch.request());
+        assert!(batch.is_pending());
+
+        // a synchronous paint in the meantime leaves nothing for the frame
+        batch.take();
+        assert!(!batch.take());
+    }
+
+    #[test]
+    fn pool_stays_bounded() {
+        let count = 10_000;
+        let mut state = TreeState {
+            count,
+            size: (300, 240),
+            offsets: RowOffsets::uniform(count, 24),
+            ..Default::default()
+        };
+
+        // the row bookkeeping of `update` while scrolling end to end, rows
+        // come from the pool first
+        let limit = state.pool_limit();
+        let mut max_pool = 0;
+
+        for offset in (0..count * 24).step_by(100) {
+            state.set_scroll(offset, 0);
+            let range = state.visible_range();
+
+            for key in range.clone() {
+                if !state.rendered.contains_key(&key) {
+                    let mut row = state.pool.pop().unwrap_or_else(|| detached_row(key, None));
+                    row.key = key;
+                    state.rendered.insert(key, row);
+                }
+            }
+
+            for (_, row) in state.take_unvisited(&range.collect()) {
+                state.pool_row(row);
+            }
+
+            max_pool = max_pool.max(state.pool.len());
+        }
+
+        assert_eq!(limit, 24);
+        assert!(max_pool <= limit);
+        assert!(!state.pool.is_empty());
+    }
+
+    #[test]
+    fn pooled_row_drops_stale_highlight() {
+        let mut state = TreeState {
+            max_pool_size: Some(4),
+            ..Default::default()
+        };
+        let mut row = detached_row(1, None);
+        row.hashes.title = 7;
+        row.title_ranges = Some(vec![0..2, 4..5]);
+        state.pool_row(row);
+
+        // the next item shares the title but not the query
+        let row = state.pool.pop().unwrap();
+        assert!(row.title_stale(7, &None));
+        assert!(row.title_stale(7, &Some(vec![0..2, 4..6])));
+        assert!(!row.title_stale(7, &Some(vec![0..2, 4..5])));
+        assert!(row.title_stale(8, &Some(vec![0..2, 4..5])));
+    }
+
+    #[test]
+    fn rapid_resizes_paint_once() {
+        let mut state = TreeState {
+            count: 100,
+            offsets: RowOffsets::uniform(100, 24),
+            ..Default::default()
+        };
+
+        // replays `update_size` during a window drag
+        let batch = FrameBatch::default();
+        let mut frames = 0;
+        for height in (100..=480).step_by(20) {
+            if state.set_size(300, height) && batch.request() {
+                frames += 1;
+            }
+        }
+
+        let mut paints = 0;
+        if batch.take() {
+            paints += 1;
+        }
+
+        assert_eq!((frames, paints), (1, 1));
+        assert_eq!(state.size, (300, 480));
+        assert_eq!(state.visible_range(), 0..22);
+
+        // the same size again doesn't schedule anything
+        assert!(!state.set_size(300, 480));
+    }
+
+    // Calls back into the state on every read, like a view that gets a
+    // synchronous notification while the controller answers
+    struct Reentrant {
+        tree: Rc<DynamicTree>,
+        state: Rc<RefCell<TreeState>>,
+    }
+
+    impl TreeController for Reentrant {
+        fn item(&self, index: usize) -> Rc<dyn TreeItem> {
+            self.state.borrow_mut().set_size(300, 240);
+            self.tree.item(index)
+        }
+
+        fn count(&self) -> usize {
+            self.state.borrow_mut().offsets_stale = true;
+            self.tree.count()
+        }
+
+        fn handle_click(&self, key: usize, modifiers: ClickModifiers) {
+            self.tree.handle_click(key, modifiers)
+        }
+
+        fn add_subscriber(&self, subscriber: Rc<dyn TreeSubscriber>) {
+            self.tree.add_subscriber(subscriber)
+        }
+
+        fn remove_subscriber(&self, subscriber: &Rc<dyn TreeSubscriber>) {
+            self.tree.remove_subscriber(subscriber)
+        }
+
+        fn parent(&self, key: KeyType) -> Option<KeyType> {
+            self.state.borrow_mut().focused = Some(key);
+            self.tree.parent(key)
+        }
+
+        fn index_of(&self, key: KeyType) -> Option<usize> {
+            self.tree.index_of(key)
+        }
+
+        fn is_selected(&self, key: KeyType) -> bool {
+            self.state.borrow_mut().focused = Some(key);
+            self.tree.is_selected(key)
+        }
+    }
+
+    #[test]
+    fn controller_reenters_during_read() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![node_with_flags(root, 1, "1", folder)
+                .build(|parent| vec![node(parent, 11, "1.1"), node(parent, 12, "1.2")])]
+        });
+
+        let state = Rc::new(RefCell::new(TreeState {
+            size: (300, 240),
+            offset: 24,
+            offsets: RowOffsets::uniform(3, 24),
+            ..Default::default()
+        }));
+
+        let ctrl = Reentrant {
+            tree: DynamicTree::new(root, Default::default()),
+            state: state.clone(),
+        };
+
+        let frame = FrameRows::read(&ctrl, &state, true);
+        let keys = frame
+            .rows
+            .iter()
+            .map(|(_, item, _)| item.key())
+            .collect::<Vec<_>>();
+
+        assert_eq!(keys, [11, 12]);
+        assert_eq!(frame.offset, 24);
+        assert_eq!(frame.ancestors.len(), 1);
+        assert_eq!(frame.ancestors[0].1.key(), 1);
+        assert_eq!(state.borrow().count, 3);
+    }
+
+    #[test]
+    fn malformed_data_key() {
+        assert_eq!(parse_key("42"), Ok(42));
+        assert_eq!(parse_key(""), Err(TreeError::InvalidKey("".into())));
+        assert_eq!(parse_key("-1"), Err(TreeError::InvalidKey("-1".into())));
+        assert_eq!(
+            parse_key("1e3; drop"),
+            Err(TreeError::InvalidKey("1e3; drop".into()))
+        );
+    }
+
+    #[test]
+    fn aria_follows_expansion() {
+        let folder = TreeFlags::EXPANDABLE | TreeFlags::EXPANDED;
+        let root = expanded_root().build(|root| {
+            vec![
+                node_with_flags(root, 1, "1", folder).build(|parent| {
+                    vec![
+                        node_with_flags(parent, 11, "1.1", TreeFlags::EXPANDABLE)
+                            .build(|parent| vec![node(parent, 111, "1.1.1")]),
+                        node(parent, 12, "1.2"),
+                    ]
+                }),
+                node(root, 2, "2"),
+            ]
+        });
+
+        let tree = DynamicTree::new(root, Default::default());
+        let aria = |index: usize| AriaAttrs::of(&*tree.item(index));
+
+        assert_eq!(
+            aria(0),
+            AriaAttrs {
+                level: 1,
+                expanded: Some(true),
+                position: Some((1, 2)),
+            }
+        );
+        assert_eq!(aria(1).level, 2);
+        assert_eq!(aria(1).expanded, Some(false));
+        assert_eq!(aria(2).position, Some((2, 2)));
+        assert_eq!(aria(3).expanded, None);
+
+        tree.expand(11);
+        assert_eq!(aria(1).expanded, Some(true));
+        assert_eq!(
+            aria(2),
+            AriaAttrs {
+                level: 3,
+                expanded: None,
+                position: Some((1, 1)),
+            }
+        );
+
+        tree.collapse(1);
+        assert_eq!(aria(0).expanded, Some(false));
+        assert_eq!(
+            aria(1),
+            AriaAttrs {
+                level: 1,
+                expanded: None,
+                position: Some((2, 2)),
+            }
+        );
+    }
+
+    #[test]
+    fn open_folder_icon() {
+        let root = expanded_root();
+        let folder = TreeNode::new(
+            &root,
+            Box::new(TestData {
+                flags: TreeFlags::EXPANDABLE,
+                icon: Some("folder"),
+                icon_expanded: Some("folder-open"),
+                ..TestData::new(1, "src")
+            }),
+        )
+        .build(|parent| vec![node(parent, 11, "main.rs")]);
+
+        let root = root.build(|root| vec![folder, node(root, 2, "Cargo.toml")]);
+        let tree = DynamicTree::new(root, Default::default());
+        let icon = |index: usize| displayed_icon(&*tree.item(index)).map(|icon| icon.to_string());
+
+        assert_eq!(icon(0).as_deref(), Some("folder"));
+        let hashes = ItemHashes::of(&*tree.item(0));
+
+        tree.expand(1);
+        assert_eq!(icon(0).as_deref(), Some("folder-open"));
+        // the swap is driven by `expanded`, the hashes stay the same
+        assert_eq!(ItemHashes::of(&*tree.item(0)), hashes);
+        assert!(icon(1).is_none());
+
+        tree.expand(1);
+        assert_eq!(icon(0).as_deref(), Some("folder"));
+
+        // no expanded icon falls back to the regular one
+        assert!(tree.item(1).icon_expanded().is_none());
+    }
+
+    #[test]
+    fn custom_theme_arrow() {
+        let theme = TreeTheme::default();
+        assert_eq!(theme.arrow(true), "iconoir-nav-arrow-down");
+        assert_eq!(theme.arrow(false), "iconoir-nav-arrow-right");
+
+        let options = TreeViewOptions {
+            theme: TreeTheme {
+                item: "row".into(),
+                arrow_expanded: "chevron-down".into(),
+                arrow_collapsed: "chevron-right".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(options.theme.arrow(true), "chevron-down");
+        assert_eq!(options.theme.arrow(false), "chevron-right");
+        assert_eq!(options.theme.container, "tree");
+        assert_eq!(options.theme.item, "row");
+        assert!(options.theme.arrow_renderer.is_none());
+    }
+
+    #[test]
+    fn badge_changes_detail_hash() {
+        // a row renderer showing `detail` relies on the detail hash alone
+        let root = expanded_root();
+        let badged = TreeNode::new(&root, Box::new(TestData::new(1, "1")));
+        let before = ItemHashes::of(&*badged);
+
+        let data = TestData {
+            detail: 3,
+            ..TestData::new(1, "1")
+        };
+        assert!(badged.set_data_if_changed(Box::new(data)));
+        let after = ItemHashes::of(&*badged);
+        assert_ne!(after.detail, before.detail);
+        assert_eq!(after.title, before.title);
+    }
+
+    // Nothing in the DOM is touched while the row isn't updated or styled
+    fn detached_row(key: KeyType, renderer: Option<&Rc<dyn RowRenderer>>) -> RenderedItem {
+        RenderedItem {
+            container: detached(),
+            key,
+            position: None,
+            hashes: ItemHashes::default(),
+            aria: AriaAttrs::default(),
+            expandable: false,
+            expanded: false,
+            focused: false,
+            hover: false,
+            selected: false,
+            sticky: false,
+            lane_copy: false,
+            check_state: None,
+            load_progress: None,
+            loading: false,
+            error: false,
+            arrow: detached(),
+            checkbox: detached(),
+            icon: detached(),
+            text_span: detached(),
+            text: detached(),
+            title_ranges: None,
+            tooltip: None,
+            edit: None,
+            spinner: detached(),
+            progress: detached(),
+            theme: Rc::new(TreeTheme::default()),
+            content: RowContent::new(renderer),
+        }
+    }
+
+    fn detached<T: JsCast>() -> T {
+        JsValue::NULL.unchecked_into()
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingRenderer(RefCell<Vec<(&'static str, Option<KeyType>)>>);
+
+    impl RecordingRenderer {
+        fn take(&self) -> Vec<(&'static str, Option<KeyType>)> {
+            std::mem::take(&mut *self.0.borrow_mut())
+        }
+    }
+
+    impl RowRenderer for RecordingRenderer {
+        fn render(&self, _container: &Element, item: &dyn TreeItem) {
+            self.0.borrow_mut().push(("render", Some(item.key())));
+        }
+
+        fn update(&self, _container: &Element, item: &dyn TreeItem) {
+            self.0.borrow_mut().push(("update", Some(item.key())));
+        }
+
+        fn recycle(&self, _container: &Element) {
+            self.0.borrow_mut().push(("recycle", None));
+        }
+    }
+
+    #[test]
+    fn row_content_lifecycle() {
+        let recorder = Rc::new(RecordingRenderer::default());
+        let renderer: Rc<dyn RowRenderer> = recorder.clone();
+        let container: Element = detached();
+        let root = expanded_root();
+        let (one, two) = (node(&root, 1, "1"), node(&root, 2, "2"));
+
+        // warmed up rows have nothing to recycle
+        let mut content = RowContent::new(Some(&renderer));
+        content.recycle(&container);
+        assert!(recorder.take().is_empty());
+
+        content.bind(&container, &*one, false);
+        assert_eq!(recorder.take(), [("render", Some(1))]);
+
+        content.bind(&container, &*one, false);
+        assert!(recorder.take().is_empty());
+
+        content.bind(&container, &*one, true);
+        assert_eq!(recorder.take(), [("update", Some(1))]);
+
+        // a recycled row is updated even when the hashes happen to match
+        content.recycle(&container);
+        content.bind(&container, &*two, false);
+        assert_eq!(recorder.take(), [("recycle", None), ("update", Some(2))]);
+    }
+
+    #[test]
+    fn pooled_rows_recycle_content() {
+        let recorder = Rc::new(RecordingRenderer::default());
+        let renderer: Rc<dyn RowRenderer> = recorder.clone();
+        let root = expanded_root();
+        let two = node(&root, 2, "2");
+
+        let mut state = TreeState {
+            max_pool_size: Some(4),
+            ..Default::default()
+        };
+
+        // a warmed up row renders on its first bind
+        state.pool.push(detached_row(0, Some(&renderer)));
+        let mut row = state.pool.pop().unwrap();
+        row.bind_content(&*node(&root, 1, "1"));
+        assert_eq!(recorder.take(), [("render", Some(1))]);
+
+        state.pool_row(row);
+        assert_eq!(recorder.take(), [("recycle", None)]);
+        assert_eq!(state.pool.len(), 1);
+
+        let mut row = state.pool.pop().unwrap();
+        row.bind_content(&*two);
+        assert_eq!(recorder.take(), [("update", Some(2))]);
+    }
+}
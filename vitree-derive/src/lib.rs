@@ -0,0 +1,163 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Index, Member};
+
+// Implements `vitree::node::TreeData` for a struct from its field roles:
+//
+//     #[derive(Debug, TreeData)]
+//     struct File {
+//         #[tree(key)]
+//         id: usize,
+//         #[tree(title)]
+//         name: String,
+//         #[tree(icon)]
+//         icon: Option<String>,
+//         #[tree(flags)]
+//         flags: TreeFlags,
+//         #[tree(detail)]
+//         size: u64,
+//     }
+//
+// `key` and `title` are required. Without an `icon` field the row has no
+// icon, without `flags` the node starts with empty flags. The hash covers
+// key, title, icon, flags, the tooltip and every `detail` field, which is
+// everything a row renders. Fields without a role are not hashed, so
+// changing them doesn't repaint the row
+#[proc_macro_derive(TreeData, attributes(tree))]
+pub fn derive_tree_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Roles {
+    key: Option<Member>,
+    title: Option<Member>,
+    icon: Option<Member>,
+    flags: Option<Member>,
+    detail: Vec<Member>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            name.span(),
+            "TreeData can only be derived for structs",
+        ));
+    };
+
+    let mut roles = Roles::default();
+    for (index, field) in data.fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        };
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("tree"))
+        {
+            attr.parse_nested_meta(|meta| {
+                let (slot, role) = if meta.path.is_ident("key") {
+                    (&mut roles.key, "key")
+                } else if meta.path.is_ident("title") {
+                    (&mut roles.title, "title")
+                } else if meta.path.is_ident("icon") {
+                    (&mut roles.icon, "icon")
+                } else if meta.path.is_ident("flags") {
+                    (&mut roles.flags, "flags")
+                } else if meta.path.is_ident("detail") {
+                    roles.detail.push(member.clone());
+                    return Ok(());
+                } else {
+                    return Err(meta.error(
+                        "unknown tree attribute, expected `key`, `title`, `icon`, `flags` or `detail`",
+                    ));
+                };
+
+                if slot.is_some() {
+                    return Err(meta.error(format!(
+                        "only one field can be marked #[tree({})]",
+                        role
+                    )));
+                }
+
+                *slot = Some(member.clone());
+                Ok(())
+            })?;
+        }
+    }
+
+    let required = |role: Option<Member>, attr: &str| {
+        role.ok_or_else(|| {
+            syn::Error::new(
+                name.span(),
+                format!("TreeData needs a field marked #[tree({})]", attr),
+            )
+        })
+    };
+
+    let key = required(roles.key, "key")?;
+    let title = required(roles.title, "title")?;
+
+    let icon = match &roles.icon {
+        Some(icon) => quote!(::vitree::derive::AsIcon::as_icon(&self.#icon)),
+        None => quote!(::core::option::Option::None),
+    };
+
+    let flags = match &roles.flags {
+        Some(flags) => quote!(self.#flags),
+        None => quote!(::vitree::node::TreeFlags::empty()),
+    };
+
+    let detail = &roles.detail;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::vitree::node::TreeData for #name #ty_generics #where_clause {
+            fn key(&self) -> ::vitree::KeyType {
+                self.#key
+            }
+
+            fn icon(&self) -> ::core::option::Option<&str> {
+                #icon
+            }
+
+            fn title(&self) -> &str {
+                ::core::convert::AsRef::<str>::as_ref(&self.#title)
+            }
+
+            fn flags(&self) -> ::vitree::node::TreeFlags {
+                #flags
+            }
+
+            fn hash(&self) -> ::vitree::HashType {
+                ::vitree::derive::hash64(&(
+                    self.#key,
+                    ::vitree::node::TreeData::title(self),
+                    ::vitree::node::TreeData::icon(self),
+                    ::vitree::node::TreeData::flags(self),
+                    ::vitree::node::TreeData::tooltip(self),
+                    #(&self.#detail,)*
+                ))
+            }
+
+            fn title_hash(&self) -> ::vitree::HashType {
+                ::vitree::derive::hash64(::vitree::node::TreeData::title(self))
+            }
+
+            fn icon_hash(&self) -> ::vitree::HashType {
+                ::vitree::derive::hash64(&::vitree::node::TreeData::icon(self))
+            }
+
+            fn detail_hash(&self) -> ::vitree::HashType {
+                ::vitree::derive::hash64(&(#(&self.#detail,)*))
+            }
+        }
+    })
+}
@@ -0,0 +1,13 @@
+use vitree::derive::TreeData;
+
+#[derive(Debug, TreeData)]
+struct File {
+    #[tree(key)]
+    id: usize,
+    #[tree(key)]
+    inode: usize,
+    #[tree(title)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use vitree::derive::TreeData;
+
+#[derive(Debug, TreeData)]
+enum Node {
+    File,
+}
+
+fn main() {}
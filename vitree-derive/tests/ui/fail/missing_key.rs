@@ -0,0 +1,9 @@
+use vitree::derive::TreeData;
+
+#[derive(Debug, TreeData)]
+struct File {
+    #[tree(title)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use vitree::derive::TreeData;
+
+#[derive(Debug, TreeData)]
+struct File {
+    #[tree(key)]
+    id: usize,
+    #[tree(name)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,46 @@
+use vitree::derive::TreeData;
+use vitree::node::{TreeData as _, TreeFlags};
+
+#[derive(Debug, TreeData)]
+struct File {
+    #[tree(key)]
+    id: usize,
+    #[tree(title)]
+    name: String,
+    #[tree(icon)]
+    icon: Option<String>,
+    #[tree(flags)]
+    flags: TreeFlags,
+    #[tree(detail)]
+    size: u64,
+    // not rendered, so not hashed
+    opened_at: u64,
+}
+
+fn main() {
+    let mut file = File {
+        id: 1,
+        name: "lib.rs".into(),
+        icon: Some("rust".into()),
+        flags: TreeFlags::empty(),
+        size: 10,
+        opened_at: 0,
+    };
+
+    assert_eq!(file.key(), 1);
+    assert_eq!(file.title(), "lib.rs");
+    assert_eq!(file.icon(), Some("rust"));
+
+    let hash = file.hash();
+    let title_hash = file.title_hash();
+
+    file.opened_at = 100;
+    assert_eq!(file.hash(), hash);
+
+    file.size = 20;
+    assert_ne!(file.hash(), hash);
+    assert_eq!(file.title_hash(), title_hash);
+
+    file.icon = Some(String::new());
+    assert_eq!(file.icon(), None);
+}
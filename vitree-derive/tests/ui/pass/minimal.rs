@@ -0,0 +1,15 @@
+use vitree::derive::TreeData;
+use vitree::node::{TreeData as _, TreeFlags};
+
+#[derive(Debug, TreeData)]
+struct Group(#[tree(key)] usize, #[tree(title)] &'static str);
+
+fn main() {
+    let group = Group(7, "Recent");
+
+    assert_eq!(group.key(), 7);
+    assert_eq!(group.title(), "Recent");
+    assert_eq!(group.icon(), None);
+    assert_eq!(group.flags(), TreeFlags::empty());
+    assert_ne!(group.hash(), Group(7, "Pinned").hash());
+}